@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 
@@ -11,86 +10,234 @@ fn main() {
   let data = content
     .split_terminator(' ')
     .map(|s| s.parse::<usize>().unwrap())
-    .collect();
+    .collect::<Vec<usize>>();
 
-  let mut tree = HashMap::new();
-  build_tree(&data, &mut 0, &mut 1, &mut tree);
+  let tree = build_tree(&data).expect("Invalid license file");
   println!(
     "Day 8: Memory Maneuver part1 solution\n {}",
-    calculate_metadata_sum(&tree, 1)
+    calculate_metadata_sum(&tree, 0)
   );
 
   println!(
     "Day 8: Memory Maneuver part2 solution\n {}",
-    calculate_node_value(&tree, 1)
+    calculate_node_value(&tree, 0)
   );
+
+  if std::env::args().any(|arg| arg == "--render") {
+    print!("{}", render_tree(&tree));
+    for id in find_zero_value_nodes(&tree) {
+      println!(
+        "Node {} is worth 0 at depth {} with {} nodes below it, path {:?}",
+        id,
+        node_depth(&tree, id),
+        subtree_size(&tree, id) - 1,
+        path_to_node(&tree, id)
+      );
+    }
+  }
+  if std::env::args().any(|arg| arg == "--dot") {
+    print!("{}", render_dot(&tree));
+  }
+  if std::env::args().any(|arg| arg == "--serialize") {
+    let serialized = serialize_tree(&tree);
+    let values = serialized.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+    println!("{}", values.join(" "));
+  }
+}
+
+fn calculate_node_value(tree: &Tree, curr_node_id: usize) -> usize {
+  calculate_node_values(tree)[curr_node_id]
+}
+
+// Children always come after their parent in the arena, so walking it
+// backwards has every child's value ready before its parent needs it.
+fn calculate_node_values(tree: &Tree) -> Vec<usize> {
+  let mut values = vec![0; tree.nodes.len()];
+  for node in tree.nodes.iter().rev() {
+    values[node.id] = if node.children.is_empty() {
+      node.metadata_entries.iter().sum()
+    } else {
+      node.metadata_entries.iter().fold(0, |acc, e| {
+        let item_index = *e;
+        if item_index > 0 && item_index <= node.children.len() {
+          acc + values[node.children[item_index - 1]]
+        } else {
+          acc
+        }
+      })
+    };
+  }
+  values
 }
 
-fn calculate_node_value(
-  tree: &HashMap<usize, Node>,
-  curr_node_id: usize,
-) -> usize {
-  let node = tree.get(&curr_node_id).unwrap();
+fn calculate_metadata_sum(tree: &Tree, curr_node_id: usize) -> usize {
   let mut sum = 0;
-  if node.children.len() == 0 {
-    sum = node.metadata_entries.iter().fold(sum, |acc, e| acc + e);
-  } else {
-    sum = node.metadata_entries.iter().fold(sum, |acc, e| {
-      let mut val = 0;
-      let item_index = *e;
-      if item_index > 0 && item_index <= node.children.len() {
-        val = calculate_node_value(tree, node.children[item_index - 1]);
-      }
-      acc + val
-    });
+  let mut stack = vec![curr_node_id];
+  while let Some(id) = stack.pop() {
+    let node = &tree.nodes[id];
+    sum += node.metadata_entries.iter().sum::<usize>();
+    stack.extend(node.children.iter());
   }
   sum
 }
 
-fn calculate_metadata_sum(
-  tree: &HashMap<usize, Node>,
-  curr_node_id: usize,
-) -> usize {
-  let node = tree.get(&curr_node_id).unwrap();
-  let mut sum = node.metadata_entries.iter().fold(0, |acc, e| acc + e);
-  sum = node
-    .children
+fn node_depth(tree: &Tree, node_id: usize) -> usize {
+  path_to_node(tree, node_id).len() - 1
+}
+
+fn subtree_size(tree: &Tree, node_id: usize) -> usize {
+  let mut size = 0;
+  let mut stack = vec![node_id];
+  while let Some(id) = stack.pop() {
+    size += 1;
+    stack.extend(tree.nodes[id].children.iter());
+  }
+  size
+}
+
+// Node ids from the root down to `node_id`, both included.
+fn path_to_node(tree: &Tree, node_id: usize) -> Vec<usize> {
+  let mut path = vec![node_id];
+  while let Some(parent) = tree.nodes[*path.last().unwrap()].parent {
+    path.push(parent);
+  }
+  path.reverse();
+  path
+}
+
+// Nodes with children that end up worth nothing while at least one of their
+// metadata entries points past their children.
+fn find_zero_value_nodes(tree: &Tree) -> Vec<usize> {
+  let values = calculate_node_values(tree);
+  tree
+    .nodes
     .iter()
-    .fold(sum, |acc, c| acc + calculate_metadata_sum(tree, *c));
-  sum
+    .filter(|node| {
+      values[node.id] == 0
+        && !node.children.is_empty()
+        && node
+          .metadata_entries
+          .iter()
+          .any(|&e| e == 0 || e > node.children.len())
+    })
+    .map(|node| node.id)
+    .collect()
 }
 
-fn build_tree(
-  data: &Vec<usize>,
-  curr_index: &mut usize,
-  curr_node: &mut usize,
-  tree: &mut HashMap<usize, Node>,
-) {
-  if *curr_index >= data.len() {
-    return;
+fn render_tree(tree: &Tree) -> String {
+  let values = calculate_node_values(tree);
+  let mut res = String::new();
+  let mut stack = vec![(0, 0)];
+  while let Some((id, depth)) = stack.pop() {
+    let node = &tree.nodes[id];
+    res.push_str(&format!(
+      "{}{} (value {}, metadata {:?})\n",
+      "  ".repeat(depth),
+      id,
+      values[id],
+      node.metadata_entries
+    ));
+    stack.extend(node.children.iter().rev().map(|&c| (c, depth + 1)));
   }
-  let mut node = Node::new(*curr_node);
-  *curr_node += 1;
-  let children_count = data[*curr_index];
-  *curr_index += 1;
+  res
+}
 
-  let metadata_count = data[*curr_index];
+fn render_dot(tree: &Tree) -> String {
+  let values = calculate_node_values(tree);
+  let mut res = String::from("digraph tree {\n");
+  for node in tree.nodes.iter() {
+    res.push_str(&format!(
+      "  n{} [label=\"{}\\nvalue {}\\nmetadata {:?}\"];\n",
+      node.id, node.id, values[node.id], node.metadata_entries
+    ));
+    for child in node.children.iter() {
+      res.push_str(&format!("  n{} -> n{};\n", node.id, child));
+    }
+  }
+  res.push_str("}\n");
+  res
+}
 
-  for _ in 0..children_count {
-    *curr_index += 1;
-    node.children.push(*curr_node);
-    build_tree(data, curr_index, curr_node, tree);
+fn build_tree(data: &[usize]) -> Result<Tree, ParseError> {
+  let read = |index: usize| {
+    data
+      .get(index)
+      .copied()
+      .ok_or(ParseError::Truncated { index })
+  };
+  let mut tree = Tree { nodes: Vec::new() };
+  // (node id, children left to read, metadata count)
+  let mut stack: Vec<(usize, usize, usize)> = Vec::new();
+  let mut curr_index = 0;
+  loop {
+    if let Some(&(id, 0, metadata_count)) = stack.last() {
+      stack.pop();
+      for _ in 0..metadata_count {
+        let entry = read(curr_index)?;
+        tree.nodes[id].metadata_entries.push(entry);
+        curr_index += 1;
+      }
+      if stack.is_empty() {
+        if curr_index < data.len() {
+          return Err(ParseError::TrailingData { index: curr_index });
+        }
+        return Ok(tree);
+      }
+    } else {
+      let children_count = read(curr_index)?;
+      let metadata_count = read(curr_index + 1)?;
+      curr_index += 2;
+      let id = tree.nodes.len();
+      let mut node = Node::new(id);
+      if let Some((parent, children_left, _)) = stack.last_mut() {
+        *children_left -= 1;
+        node.parent = Some(*parent);
+        tree.nodes[*parent].children.push(id);
+      }
+      tree.nodes.push(node);
+      stack.push((id, children_count, metadata_count));
+    }
   }
-  for _ in 1..=metadata_count {
-    *curr_index += 1;
-    node.metadata_entries.push(data[*curr_index]);
+}
+
+fn serialize_tree(tree: &Tree) -> Vec<usize> {
+  let mut data = Vec::new();
+  // (node id, index of the next child to write)
+  let mut stack = vec![(0, 0)];
+  while let Some((id, next_child)) = stack.pop() {
+    let node = &tree.nodes[id];
+    if next_child == 0 {
+      data.push(node.children.len());
+      data.push(node.metadata_entries.len());
+    }
+    if next_child < node.children.len() {
+      stack.push((id, next_child + 1));
+      stack.push((node.children[next_child], 0));
+    } else {
+      data.extend(node.metadata_entries.iter());
+    }
   }
-  tree.insert(node.id, node);
+  data
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseError {
+  // the data ended while a value at `index` was still expected
+  Truncated { index: usize },
+  // the root node ended at `index` with data still left after it
+  TrailingData { index: usize },
+}
+
+// Nodes are stored in the order they appear in the data, the root being 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tree {
+  nodes: Vec<Node>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Node {
   id: usize,
+  parent: Option<usize>,
   children: Vec<usize>,
   metadata_entries: Vec<usize>,
 }
@@ -98,6 +245,7 @@ impl Node {
   pub fn new(id: usize) -> Node {
     Node {
       id,
+      parent: None,
       children: Vec::new(),
       metadata_entries: Vec::new(),
     }
@@ -108,27 +256,102 @@ impl Node {
 mod test {
   use super::*;
 
+  fn example_data() -> Vec<usize> {
+    vec![2, 3, 0, 3, 10, 11, 12, 1, 1, 0, 1, 99, 2, 1, 1, 2]
+  }
+
   #[test]
   fn part1_test() {
-    let mut tree = HashMap::new();
-    build_tree(
-      &vec![2, 3, 0, 3, 10, 11, 12, 1, 1, 0, 1, 99, 2, 1, 1, 2],
-      &mut 0,
-      &mut 1,
-      &mut tree,
-    );
-    assert_eq!(calculate_metadata_sum(&tree, 1), 138);
+    let tree = build_tree(&example_data()).unwrap();
+    assert_eq!(calculate_metadata_sum(&tree, 0), 138);
   }
 
   #[test]
   fn part2_test() {
-    let mut tree = HashMap::new();
-    build_tree(
-      &vec![2, 3, 0, 3, 10, 11, 12, 1, 1, 0, 1, 99, 2, 1, 1, 2],
-      &mut 0,
-      &mut 1,
-      &mut tree,
+    let tree = build_tree(&example_data()).unwrap();
+    assert_eq!(calculate_node_value(&tree, 0), 66);
+  }
+
+  #[test]
+  fn round_trip_test() {
+    let tree = build_tree(&example_data()).unwrap();
+    assert_eq!(tree.nodes.len(), 4);
+    assert_eq!(tree.nodes[0].children, vec![1, 2]);
+    assert_eq!(tree.nodes[2].children, vec![3]);
+    assert_eq!(serialize_tree(&tree), example_data());
+  }
+
+  #[test]
+  fn queries_test() {
+    let tree = build_tree(&example_data()).unwrap();
+    assert_eq!(node_depth(&tree, 0), 0);
+    assert_eq!(node_depth(&tree, 3), 2);
+    assert_eq!(subtree_size(&tree, 0), 4);
+    assert_eq!(subtree_size(&tree, 2), 2);
+    assert_eq!(path_to_node(&tree, 3), vec![0, 2, 3]);
+    assert_eq!(find_zero_value_nodes(&tree), vec![2]);
+  }
+
+  #[test]
+  fn render_test() {
+    let tree = build_tree(&example_data()).unwrap();
+    assert_eq!(
+      render_tree(&tree),
+      "0 (value 66, metadata [1, 1, 2])
+  1 (value 33, metadata [10, 11, 12])
+  2 (value 0, metadata [2])
+    3 (value 99, metadata [99])
+"
+    );
+    let dot = render_dot(&tree);
+    assert!(dot.starts_with("digraph tree {\n"));
+    assert!(dot.contains("  n2 [label=\"2\\nvalue 0\\nmetadata [2]\"];\n"));
+    assert!(dot.contains("  n2 -> n3;\n"));
+  }
+
+  #[test]
+  fn truncated_test() {
+    let data = example_data();
+    assert_eq!(
+      build_tree(&data[..data.len() - 1]),
+      Err(ParseError::Truncated { index: 15 })
+    );
+    assert_eq!(
+      build_tree(&[1, 1, 0]),
+      Err(ParseError::Truncated { index: 3 })
+    );
+    assert_eq!(build_tree(&[]), Err(ParseError::Truncated { index: 0 }));
+  }
+
+  #[test]
+  fn trailing_data_test() {
+    assert_eq!(
+      build_tree(&[0, 1, 5, 99, 7]),
+      Err(ParseError::TrailingData { index: 3 })
     );
-    assert_eq!(calculate_node_value(&tree, 1), 66);
+    let mut data = example_data();
+    data.push(0);
+    assert_eq!(
+      build_tree(&data),
+      Err(ParseError::TrailingData { index: 16 })
+    );
+  }
+
+  #[test]
+  fn deep_tree_test() {
+    let depth = 1_000_000;
+    let mut data = Vec::new();
+    for _ in 0..depth {
+      data.push(1);
+      data.push(1);
+    }
+    data[depth * 2 - 2] = 0;
+    data.extend(vec![1; depth]);
+
+    let tree = build_tree(&data).unwrap();
+    assert_eq!(tree.nodes.len(), depth);
+    assert_eq!(calculate_metadata_sum(&tree, 0), depth);
+    assert_eq!(calculate_node_value(&tree, 0), 1);
+    assert_eq!(serialize_tree(&tree), data);
   }
 }
@@ -1,5 +1,3 @@
-use std::collections::VecDeque;
-
 fn main() {
   // Input:
   // 459 players; last marble is worth 72103 points
@@ -16,30 +14,178 @@ fn main() {
 }
 
 fn find_high_score(last_marble: usize, players: usize) -> usize {
-  let mut circle = VecDeque::new();
-  circle.push_front(0);
-  let mut player_points = vec![0; players];
+  play_game(last_marble, players, &Rules::default(), false).high_score
+}
+
+fn play_game(
+  last_marble: usize,
+  players: usize,
+  rules: &Rules,
+  record_scoring_turns: bool,
+) -> GameResult {
+  if rules.insert_offset == 0 {
+    panic!("marbles have to be inserted at least one step clockwise");
+  }
+  if rules.special_rules.iter().any(|r| r.multiple == 0) {
+    panic!("special multiples have to be positive");
+  }
+  let mut circle = MarbleCircle::new(last_marble);
+  let mut scores = vec![0; players];
+  let mut scoring_turns = Vec::new();
   for i in 1..=last_marble {
-    if i % 23 == 0 {
-      circle.rotate_right(7);
-      let val = circle.pop_front().expect("Couldn't pop value");
-      player_points[i % players] += i + val;
+    let player = (i - 1) % players;
+    let special_rule = rules.special_rules.iter().find(|r| i % r.multiple == 0);
+    if let Some(rule) = special_rule {
+      let mut val = 0;
+      if circle.len > 1 {
+        circle.rotate_counter_clockwise(rule.counter_clockwise_offset);
+        val = circle.remove_current();
+      }
+      scores[player] += i + val;
+      if record_scoring_turns {
+        scoring_turns.push(ScoringTurn {
+          marble: i,
+          player: player + 1,
+          points: i + val,
+        });
+      }
+    } else {
+      circle.rotate_clockwise(rules.insert_offset - 1);
+      circle.insert_after_current(i);
+    }
+  }
+
+  let (winner, high_score) = scores
+    .iter()
+    .enumerate()
+    .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(&a.0)))
+    .map(|(player, score)| (player + 1, *score))
+    .expect("Couldn't find max value");
+  GameResult {
+    scores,
+    winner,
+    high_score,
+    scoring_turns: if record_scoring_turns {
+      Some(scoring_turns)
     } else {
-      (0..2).for_each(|_| {
-        let item = circle.pop_front().expect("Couldn't pop value");
-        circle.push_back(item);
-      });
-      circle.push_front(i);
+      None
+    },
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SpecialRule {
+  multiple: usize,
+  counter_clockwise_offset: usize,
+}
+
+// A marble matching several special rules is handled by the first one. A
+// special marble never takes the last marble out of the circle, it only scores
+// itself then.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rules {
+  // how many steps clockwise of the current marble a new one is placed
+  insert_offset: usize,
+  special_rules: Vec<SpecialRule>,
+}
+impl Default for Rules {
+  fn default() -> Self {
+    Self {
+      insert_offset: 2,
+      special_rules: vec![SpecialRule {
+        multiple: 23,
+        counter_clockwise_offset: 7,
+      }],
+    }
+  }
+}
+
+// Players are numbered from 1, `scores[0]` belongs to player 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GameResult {
+  scores: Vec<usize>,
+  winner: usize,
+  high_score: usize,
+  scoring_turns: Option<Vec<ScoringTurn>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ScoringTurn {
+  marble: usize,
+  player: usize,
+  points: usize,
+}
+
+// Doubly linked circle where every marble is its own slot in the arena, so a
+// game up to `last_marble` allocates exactly two links per marble up front.
+#[derive(Debug, Clone)]
+struct MarbleCircle {
+  next: Vec<u32>,
+  prev: Vec<u32>,
+  current: u32,
+  len: usize,
+}
+impl MarbleCircle {
+  pub fn new(last_marble: usize) -> Self {
+    Self {
+      next: vec![0; last_marble + 1],
+      prev: vec![0; last_marble + 1],
+      current: 0,
+      len: 1,
+    }
+  }
+
+  pub fn rotate_clockwise(&mut self, steps: usize) {
+    for _ in 0..steps {
+      self.current = self.next[self.current as usize];
     }
   }
 
-  *player_points.iter().max().expect("Couldn't find max value")
+  pub fn rotate_counter_clockwise(&mut self, steps: usize) {
+    for _ in 0..steps {
+      self.current = self.prev[self.current as usize];
+    }
+  }
+
+  // Places `marble` clockwise of the current one and makes it current.
+  pub fn insert_after_current(&mut self, marble: usize) {
+    let before = self.current as usize;
+    let after = self.next[before] as usize;
+    self.next[marble] = after as u32;
+    self.prev[marble] = before as u32;
+    self.next[before] = marble as u32;
+    self.prev[after] = marble as u32;
+    self.current = marble as u32;
+    self.len += 1;
+  }
+
+  // Takes out the current marble; the one clockwise of it becomes current.
+  pub fn remove_current(&mut self) -> usize {
+    let removed = self.current as usize;
+    let before = self.prev[removed] as usize;
+    let after = self.next[removed] as usize;
+    self.next[before] = after as u32;
+    self.prev[after] = before as u32;
+    self.current = after as u32;
+    self.len -= 1;
+    removed
+  }
 }
 
 #[cfg(test)]
 mod test {
   use super::*;
 
+  fn marbles(circle: &MarbleCircle) -> Vec<usize> {
+    let mut marbles = vec![0];
+    let mut curr = circle.next[0] as usize;
+    while curr != 0 {
+      marbles.push(curr);
+      curr = circle.next[curr] as usize;
+    }
+    marbles
+  }
+
   #[test]
   fn part1_test1() {
     assert_eq!(find_high_score(25, 9), 32);
@@ -69,4 +215,127 @@ mod test {
   fn part1_test6() {
     assert_eq!(find_high_score(5807, 30), 37305);
   }
+
+  #[test]
+  fn game_result_test() {
+    let result = play_game(25, 9, &Rules::default(), true);
+    assert_eq!(result.scores, vec![0, 0, 0, 0, 32, 0, 0, 0, 0]);
+    assert_eq!(result.winner, 5);
+    assert_eq!(result.high_score, 32);
+    assert_eq!(
+      result.scoring_turns,
+      Some(vec![ScoringTurn {
+        marble: 23,
+        player: 5,
+        points: 32,
+      }])
+    );
+    assert_eq!(
+      play_game(25, 9, &Rules::default(), false).scoring_turns,
+      None
+    );
+  }
+
+  #[test]
+  fn marble_circle_test() {
+    let mut circle = MarbleCircle::new(25);
+    for i in 1..=22 {
+      circle.rotate_clockwise(1);
+      circle.insert_after_current(i);
+    }
+    assert_eq!(
+      marbles(&circle),
+      vec![
+        0, 16, 8, 17, 4, 18, 9, 19, 2, 20, 10, 21, 5, 22, 11, 1, 12, 6, 13, 3,
+        14, 7, 15
+      ]
+    );
+    circle.rotate_counter_clockwise(7);
+    assert_eq!(circle.remove_current(), 9);
+    assert_eq!(circle.current, 19);
+  }
+
+  // Straightforward Vec based game to compare the linked circle against.
+  fn reference_scores(
+    last_marble: usize,
+    players: usize,
+    rules: &Rules,
+  ) -> Vec<usize> {
+    let mut circle = vec![0];
+    let mut current = 0;
+    let mut scores = vec![0; players];
+    for i in 1..=last_marble {
+      let special_rule =
+        rules.special_rules.iter().find(|r| i % r.multiple == 0);
+      if let Some(rule) = special_rule {
+        let len = circle.len();
+        let mut val = 0;
+        if len > 1 {
+          current = (current + len - rule.counter_clockwise_offset % len) % len;
+          val = circle.remove(current);
+          current %= circle.len();
+        }
+        scores[(i - 1) % players] += i + val;
+      } else {
+        current = (current + rules.insert_offset - 1) % circle.len() + 1;
+        circle.insert(current, i);
+      }
+    }
+    scores
+  }
+
+  #[test]
+  fn default_rules_property_test() {
+    let cases = [
+      (25, 9, 32),
+      (1618, 10, 8317),
+      (7999, 13, 146373),
+      (1104, 17, 2764),
+      (6111, 21, 54718),
+      (5807, 30, 37305),
+    ];
+    for &(last_marble, players, high_score) in cases.iter() {
+      let result = play_game(last_marble, players, &Rules::default(), true);
+      assert_eq!(result.high_score, high_score);
+      assert_eq!(result.scores[result.winner - 1], high_score);
+      assert_eq!(result.scores.len(), players);
+      assert!(result.scores.iter().all(|&s| s <= high_score));
+      let turns = result.scoring_turns.unwrap();
+      assert_eq!(turns.len(), last_marble / 23);
+      assert!(turns.iter().all(|t| t.marble % 23 == 0));
+      assert_eq!(
+        turns.iter().map(|t| t.points).sum::<usize>(),
+        result.scores.iter().sum::<usize>()
+      );
+      assert_eq!(
+        result.scores,
+        reference_scores(last_marble, players, &Rules::default())
+      );
+    }
+  }
+
+  #[test]
+  fn custom_rules_property_test() {
+    for insert_offset in 1..=4 {
+      for multiple in 1..=9 {
+        for counter_clockwise_offset in 0..=10 {
+          let rules = Rules {
+            insert_offset,
+            special_rules: vec![
+              SpecialRule {
+                multiple,
+                counter_clockwise_offset,
+              },
+              SpecialRule {
+                multiple: 11,
+                counter_clockwise_offset: 3,
+              },
+            ],
+          };
+          let result = play_game(300, 7, &rules, false);
+          assert_eq!(result.scores, reference_scores(300, 7, &rules));
+        }
+      }
+    }
+  }
 }
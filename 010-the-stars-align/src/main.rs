@@ -7,37 +7,83 @@ fn main() {
   let points = read_input("input.txt");
 
   let (message, time_spent) = find_message(&points);
-  println!("Day 10: The Stars Align part1 solution\n{}", message);
+  let recognition = recognise_message(&message);
+  println!(
+    "Day 10: The Stars Align part1 solution\n{}{}",
+    message, recognition.text
+  );
+  for glyph in recognition.unrecognised.iter() {
+    println!(
+      "Unrecognised glyph at {}:\n{}",
+      glyph.position, glyph.bitmap
+    );
+  }
 
   println!("Day 10: The Stars Align part2 solution\n {}", time_spent);
+
+  if std::env::args().any(|arg| arg == "--frames") {
+    let written = export_frames(&points, time_spent, FRAME_WINDOW, "frames")
+      .expect("Failed to write frames");
+    println!("Wrote {} frames to frames/", written);
+  }
 }
 
+const REFINE_WINDOW: i64 = 10;
+
 fn find_message(init_points: &Vec<Point>) -> (String, usize) {
-  let mut points = init_points.clone();
-  let mut res = init_points.clone();
-  let (min_x, max_x, min_y, max_y) = find_edge_points(&points);
-  let mut min_diff_x = max_x - min_x;
-  let mut min_diff_y = max_y - min_y;
-  let mut time = 0;
-  loop {
+  let estimate = estimate_convergence_time(init_points);
+  let from = (estimate - REFINE_WINDOW).max(0);
+  let mut points = points_at(init_points, from);
+  let mut best = (bounding_box_area(&points), from, points.clone());
+  for time in from + 1..=estimate + REFINE_WINDOW {
     move_points(&mut points);
-    let (min_x, max_x, min_y, max_y) = find_edge_points(&points);
-    let diff_x = max_x - min_x;
-    let diff_y = max_y - min_y;
-    if diff_x > min_diff_x && diff_y > min_diff_y {
-      break;
-    }
-    if diff_x < min_diff_x {
-      min_diff_x = diff_x;
+    let area = bounding_box_area(&points);
+    if area < best.0 {
+      best = (area, time, points.clone());
     }
-    if diff_y < min_diff_y {
-      min_diff_y = diff_y;
-    }
-    res = points.clone();
-    time += 1;
   }
 
-  (prepare_message(&res), time)
+  (prepare_message(&best.2), best.1 as usize)
+}
+
+// The spread of the points over time is a parabola, sum over both axes of
+// var(pos) + 2 * t * cov(pos, vel) + t^2 * var(vel), so its minimum can be
+// read straight off the covariances instead of stepping towards it.
+fn estimate_convergence_time(points: &[Point]) -> i64 {
+  let n = points.len() as f64;
+  let mean = |f: &dyn Fn(&Point) -> f64| points.iter().map(f).sum::<f64>() / n;
+  let (px, py) = (mean(&|p| p.pos_x as f64), mean(&|p| p.pos_y as f64));
+  let (vx, vy) = (mean(&|p| p.vel_x as f64), mean(&|p| p.vel_y as f64));
+  let mut cov = 0.0;
+  let mut var = 0.0;
+  for p in points.iter() {
+    let (dvx, dvy) = (p.vel_x as f64 - vx, p.vel_y as f64 - vy);
+    cov += (p.pos_x as f64 - px) * dvx + (p.pos_y as f64 - py) * dvy;
+    var += dvx * dvx + dvy * dvy;
+  }
+  if var == 0.0 {
+    return 0;
+  }
+  (-cov / var).round().max(0.0) as i64
+}
+
+fn points_at(points: &[Point], time: i64) -> Vec<Point> {
+  points
+    .iter()
+    .map(|p| {
+      Point::new(
+        (p.pos_x as i64 + p.vel_x as i64 * time) as i32,
+        (p.pos_y as i64 + p.vel_y as i64 * time) as i32,
+        p.vel_x,
+        p.vel_y,
+      )
+    })
+    .collect()
+}
+
+fn bounding_box_area(points: &Vec<Point>) -> i64 {
+  let (min_x, max_x, min_y, max_y) = find_edge_points(points);
+  (max_x - min_x) as i64 * (max_y - min_y) as i64
 }
 
 fn move_points(points: &mut Vec<Point>) {
@@ -68,6 +114,174 @@ fn prepare_message(points: &Vec<Point>) -> String {
   res
 }
 
+const FRAME_WINDOW: usize = 5;
+
+// Writes one PBM file per second from `center - window` to `center + window`,
+// all cropped to the box that holds the points of every frame so they line
+// up when played back.
+fn export_frames(
+  init_points: &[Point],
+  center: usize,
+  window: usize,
+  dir: &str,
+) -> std::io::Result<usize> {
+  let from = center.saturating_sub(window);
+  let frames = (from..=center + window)
+    .map(|time| (time, points_at(init_points, time as i64)))
+    .collect::<Vec<_>>();
+  let mut bounds = find_edge_points(&frames[0].1);
+  for (_, points) in frames.iter() {
+    let (min_x, max_x, min_y, max_y) = find_edge_points(points);
+    bounds = (
+      bounds.0.min(min_x),
+      bounds.1.max(max_x),
+      bounds.2.min(min_y),
+      bounds.3.max(max_y),
+    );
+  }
+
+  std::fs::create_dir_all(dir)?;
+  for (time, points) in frames.iter() {
+    let mut file = File::create(format!("{}/frame-{:06}.pbm", dir, time))?;
+    file.write_all(render_pbm(points, bounds).as_bytes())?;
+  }
+  Ok(frames.len())
+}
+
+// Plain PBM of the points that fall inside `(min_x, max_x, min_y, max_y)`.
+fn render_pbm(points: &[Point], bounds: (i32, i32, i32, i32)) -> String {
+  let (min_x, max_x, min_y, max_y) = bounds;
+  let width = (max_x - min_x + 1) as usize;
+  let height = (max_y - min_y + 1) as usize;
+  let mut pixels = vec![vec!['0'; width]; height];
+  for p in points {
+    if p.pos_x >= min_x
+      && p.pos_x <= max_x
+      && p.pos_y >= min_y
+      && p.pos_y <= max_y
+    {
+      pixels[(p.pos_y - min_y) as usize][(p.pos_x - min_x) as usize] = '1';
+    }
+  }
+
+  let mut res = format!("P1\n{} {}\n", width, height);
+  for row in pixels {
+    res.extend(row);
+    res.push('\n');
+  }
+  res
+}
+
+const GLYPH_HEIGHT: usize = 10;
+// Letters of a block are drawn side by side, two columns apart.
+const FONT: [(&str, [&str; GLYPH_HEIGHT]); 2] = [
+  (
+    "ABCEFGHJ",
+    [
+      "..##..  #####.  .####.  ######  ######  .####.  #....#  ...###",
+      ".#..#.  #....#  #....#  #.....  #.....  #....#  #....#  ....#.",
+      "#....#  #....#  #.....  #.....  #.....  #.....  #....#  ....#.",
+      "#....#  #....#  #.....  #.....  #.....  #.....  #....#  ....#.",
+      "#....#  #####.  #.....  #####.  #####.  #.....  ######  ....#.",
+      "######  #....#  #.....  #.....  #.....  #..###  #....#  ....#.",
+      "#....#  #....#  #.....  #.....  #.....  #....#  #....#  ....#.",
+      "#....#  #....#  #.....  #.....  #.....  #....#  #....#  #...#.",
+      "#....#  #....#  #....#  #.....  #.....  #...##  #....#  #...#.",
+      "#....#  #####.  .####.  ######  #.....  .###.#  #....#  .###..",
+    ],
+  ),
+  (
+    "KLNPRXZ",
+    [
+      "#....#  #.....  #....#  #####.  #####.  #....#  ######",
+      "#...#.  #.....  ##...#  #....#  #....#  #....#  .....#",
+      "#..#..  #.....  ##...#  #....#  #....#  .#..#.  .....#",
+      "#.#...  #.....  #.#..#  #....#  #....#  .#..#.  ....#.",
+      "##....  #.....  #.#..#  #####.  #####.  ..##..  ...#..",
+      "##....  #.....  #..#.#  #.....  #..#..  ..##..  ..#...",
+      "#.#...  #.....  #..#.#  #.....  #...#.  .#..#.  .#....",
+      "#..#..  #.....  #...##  #.....  #...#.  .#..#.  #.....",
+      "#...#.  #.....  #...##  #.....  #....#  #....#  #.....",
+      "#....#  ######  #....#  #.....  #....#  #....#  ######",
+    ],
+  ),
+];
+
+// Splits the rendered message on empty columns and looks every piece up in
+// the glyph table. Pieces that don't match any letter show up as '?' in the
+// text and are reported with their bitmap drawn with '#' and '.'.
+fn recognise_message(message: &str) -> Recognition {
+  let rows = message
+    .lines()
+    .map(|l| l.chars().map(|c| c == '#').collect::<Vec<bool>>())
+    .filter(|r| r.contains(&true))
+    .collect::<Vec<Vec<bool>>>();
+  let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+  let is_lit = |x: usize, y: usize| rows[y].get(x) == Some(&true);
+
+  let mut pieces = Vec::new();
+  let mut from = None;
+  for x in 0..=width {
+    let empty = x == width || (0..rows.len()).all(|y| !is_lit(x, y));
+    match (from, empty) {
+      (None, false) => from = Some(x),
+      (Some(start), true) => {
+        pieces.push((start, x));
+        from = None;
+      }
+      _ => {}
+    }
+  }
+
+  let mut recognition = Recognition {
+    text: String::new(),
+    unrecognised: Vec::new(),
+  };
+  for (position, (from, to)) in pieces.into_iter().enumerate() {
+    let bitmap = (0..rows.len())
+      .map(|y| {
+        (from..to)
+          .map(|x| if is_lit(x, y) { '#' } else { '.' })
+          .collect::<String>()
+      })
+      .collect::<Vec<String>>();
+    let letter = FONT.iter().find_map(|(letters, rows)| {
+      letters.chars().enumerate().find_map(|(i, letter)| {
+        let glyph = rows.iter().map(|row| &row[i * 8..i * 8 + 6]);
+        if glyph.eq(bitmap.iter().map(|r| r.as_str())) {
+          Some(letter)
+        } else {
+          None
+        }
+      })
+    });
+    match letter {
+      Some(letter) => recognition.text.push(letter),
+      None => {
+        recognition.text.push('?');
+        recognition.unrecognised.push(UnknownGlyph {
+          position,
+          bitmap: bitmap.join("\n") + "\n",
+        });
+      }
+    }
+  }
+  recognition
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Recognition {
+  text: String,
+  unrecognised: Vec<UnknownGlyph>,
+}
+
+// `position` is the index of the glyph within the message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UnknownGlyph {
+  position: usize,
+  bitmap: String,
+}
+
 fn find_edge_points(points: &Vec<Point>) -> (i32, i32, i32, i32) {
   let mut min_x = points[0].pos_x;
   let mut max_x = points[0].pos_x;
@@ -132,3 +346,73 @@ fn read_input(filename: &str) -> Vec<Point> {
     })
     .collect()
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn part2_test() {
+    let points = read_input("test-input.txt");
+    assert_eq!(find_message(&points).1, 3);
+  }
+
+  #[test]
+  fn far_convergence_test() {
+    let points = read_input("test-input.txt");
+    let (message, _) = find_message(&points);
+    let rewound = points_at(&points, -1_000_000);
+    assert_eq!(estimate_convergence_time(&rewound), 1_000_003);
+    assert_eq!(find_message(&rewound), (message, 1_000_003));
+  }
+
+  #[test]
+  fn render_pbm_test() {
+    let points = points_at(&read_input("test-input.txt"), 3);
+    let pbm = render_pbm(&points, find_edge_points(&points));
+    let mut lines = pbm.lines();
+    assert_eq!(lines.next(), Some("P1"));
+    assert_eq!(lines.next(), Some("10 8"));
+    assert_eq!(lines.next(), Some("1000100111"));
+    assert_eq!(lines.count(), 7);
+  }
+
+  #[test]
+  fn export_frames_test() {
+    let dir = std::env::temp_dir()
+      .join(format!("stars-align-frames-{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let points = read_input("test-input.txt");
+    assert_eq!(export_frames(&points, 3, 5, dir).unwrap(), 9);
+
+    let first = std::fs::read_to_string(format!("{}/frame-000000.pbm", dir));
+    let last = std::fs::read_to_string(format!("{}/frame-000008.pbm", dir));
+    let size = |pbm: String| pbm.lines().nth(1).unwrap().to_string();
+    assert_eq!(size(first.unwrap()), size(last.unwrap()));
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn recognise_message_test() {
+    let message = (0..GLYPH_HEIGHT)
+      .map(|y| format!("{}  {}", FONT[0].1[y], FONT[1].1[y]).replace('.', " "))
+      .collect::<Vec<String>>()
+      .join("\n");
+    let recognition = recognise_message(&message);
+    assert_eq!(recognition.text, "ABCEFGHJKLNPRXZ");
+    assert!(recognition.unrecognised.is_empty());
+  }
+
+  #[test]
+  fn unrecognised_glyph_test() {
+    let points = read_input("test-input.txt");
+    let (message, _) = find_message(&points);
+    let recognition = recognise_message(&message);
+    assert_eq!(recognition.text, "??");
+    assert_eq!(recognition.unrecognised[1].position, 1);
+    assert_eq!(
+      recognition.unrecognised[1].bitmap,
+      "###\n.#.\n.#.\n.#.\n.#.\n.#.\n.#.\n###\n"
+    );
+  }
+}
@@ -1,3 +1,7 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::ops::RangeInclusive;
+
 fn main() {
   // input: 9798
   let grid = generate_grid(9798);
@@ -11,72 +15,191 @@ fn main() {
     "Day 11: Chronal Charge part2 solution\n{:?}",
     find_best_fuel_square(&grid)
   );
-}
 
-fn find_best_fuel_square(grid: &Vec<Vec<i32>>) -> (usize, usize, usize) {
-  let size = grid.len();
-  let mut sums = grid.clone();
-  let mut best = (1, 1, 1);
-  let mut best_sum = i32::min_value();
-  for y in 1..size {
-    for x in 1..size {
-      sums[x][y] =
-        grid[x][y] + sums[x][y - 1] + sums[x - 1][y] - sums[x - 1][y - 1];
-    }
+  // --heatmap [size], the size defaulting to the best one overall
+  let args = std::env::args().collect::<Vec<String>>();
+  if let Some(flag) = args.iter().position(|arg| arg == "--heatmap") {
+    let size = match args.get(flag + 1) {
+      Some(size) => size.parse().expect("The heatmap size must be a number"),
+      None => find_best_fuel_square(&grid).2,
+    };
+    let best = *grid
+      .top_squares(size..=size, 1)
+      .first()
+      .expect("No square of that size fits on the grid");
+    std::fs::write("power.pgm", render_pgm(&grid.anchor_powers(1), &best))
+      .expect("Failed to write power.pgm");
+    // on the anchor heatmap the whole winning square is its anchor pixel
+    let anchor = Square { size: 1, ..best };
+    let anchors = format!("anchors-{}.pgm", size);
+    std::fs::write(&anchors, render_pgm(&grid.anchor_powers(size), &anchor))
+      .expect("Failed to write anchor heatmap");
+    println!("Wrote power.pgm and {}", anchors);
   }
-  for cell_size in 1..size {
-    for y in 1..size - cell_size {
-      for x in 1..size - cell_size {
-        let prev_x = x - 1;
-        let prev_y = y - 1;
-        let sum = sums[prev_x][prev_y]
-          + sums[prev_x + cell_size][prev_y + cell_size]
-          - sums[prev_x + cell_size][prev_y]
-          - sums[prev_x][prev_y + cell_size];
-        if sum > best_sum {
-          best_sum = sum;
-          best = (x, y, cell_size);
-        }
+}
+
+const GRID_SIZE: usize = 300;
+
+fn find_best_fuel_square(grid: &PowerGrid) -> (usize, usize, usize) {
+  let best = grid.top_squares(1..=grid.width.min(grid.height), 1)[0];
+  (best.x, best.y, best.size)
+}
+
+fn find_best_fuel_square_3x3(grid: &PowerGrid) -> (usize, usize) {
+  let best = grid.top_squares(3..=3, 1)[0];
+  (best.x, best.y)
+}
+
+fn generate_grid(grid_sn: usize) -> PowerGrid {
+  PowerGrid::new(grid_sn, GRID_SIZE, GRID_SIZE)
+}
+
+// Summed-area table of the cell powers, `sums[y][x]` holding the total of
+// every cell left of and above (x, y), both included. Row and column 0 stay
+// empty so the 1-based coordinates index straight into it.
+struct PowerGrid {
+  width: usize,
+  height: usize,
+  sums: Vec<Vec<i64>>,
+}
+impl PowerGrid {
+  pub fn new(grid_sn: usize, width: usize, height: usize) -> PowerGrid {
+    let mut sums = vec![vec![0; width + 1]; height + 1];
+    for y in 1..=height {
+      for x in 1..=width {
+        sums[y][x] = calculate_fuel_cell_power(x, y, grid_sn) as i64
+          + sums[y - 1][x]
+          + sums[y][x - 1]
+          - sums[y - 1][x - 1];
       }
     }
+
+    PowerGrid {
+      width,
+      height,
+      sums,
+    }
   }
 
-  best
-}
+  pub fn square_power(&self, x: usize, y: usize, size: usize) -> i64 {
+    let (left, top) = (x - 1, y - 1);
+    let (right, bottom) = (left + size, top + size);
+    self.sums[bottom][right] - self.sums[top][right] - self.sums[bottom][left]
+      + self.sums[top][left]
+  }
 
-fn find_best_fuel_square_3x3(grid: &Vec<Vec<i32>>) -> (usize, usize) {
-  let mut best = (1, 1);
-  let mut best_sum = i32::min_value();
-  let size = grid.len() - 1;
-  for y in 1..=size - 3 {
-    for x in 1..=size - 3 {
-      let mut sum = 0;
-      for j in 0..3 {
-        for i in 0..3 {
-          sum += grid[x + i][y + j];
+  // Power of the `size` square anchored at every cell it fits on, indexed
+  // `[y - 1][x - 1]`. A size of 1 gives the raw cell powers.
+  pub fn anchor_powers(&self, size: usize) -> Vec<Vec<i64>> {
+    if size == 0 || size > self.width || size > self.height {
+      return Vec::new();
+    }
+    (1..=self.height - size + 1)
+      .map(|y| {
+        (1..=self.width - size + 1)
+          .map(|x| self.square_power(x, y, size))
+          .collect()
+      })
+      .collect()
+  }
+
+  // The `k` most powerful squares with a size in `sizes`, best first. Ties
+  // go to the smaller square, then the one closer to the top left.
+  pub fn top_squares(
+    &self,
+    sizes: RangeInclusive<usize>,
+    k: usize,
+  ) -> Vec<Square> {
+    let mut heap = BinaryHeap::new();
+    let max_size = self.width.min(self.height).min(*sizes.end());
+    for size in (*sizes.start()).max(1)..=max_size {
+      for y in 1..=self.height - size + 1 {
+        for x in 1..=self.width - size + 1 {
+          let square = Square {
+            x,
+            y,
+            size,
+            power: self.square_power(x, y, size),
+          };
+          if heap.len() < k {
+            heap.push(Reverse(square));
+          } else if let Some(Reverse(worst)) = heap.peek() {
+            if square > *worst {
+              heap.pop();
+              heap.push(Reverse(square));
+            }
+          }
         }
       }
-      if sum > best_sum {
-        best_sum = sum;
-        best = (x, y);
-      }
     }
-  }
 
-  best
+    let mut squares = heap.into_iter().map(|s| s.0).collect::<Vec<_>>();
+    squares.sort_by(|a, b| b.cmp(a));
+    squares
+  }
 }
 
-fn generate_grid(grid_sn: usize) -> Vec<Vec<i32>> {
-  let size = 300;
-  let mut grid = vec![vec![0; size + 1]; size + 1];
-  for y in 1..=size {
-    for x in 1..=size {
-      let power_level = calculate_fuel_cell_power(x, y, grid_sn);
-      grid[x][y] = power_level;
-    }
+// Plain PGM of `values[y][x]`, darkest for the lowest value, with the edge of
+// `outline` drawn in full white on top. The outline is taken in the 1-based
+// coordinates of `values`, which for `anchor_powers(1)` are the cells
+// themselves; on a bigger size's heatmap a square covers a single anchor.
+fn render_pgm(values: &[Vec<i64>], outline: &Square) -> String {
+  let height = values.len();
+  let width = values.first().map_or(0, |row| row.len());
+  let min = values.iter().flatten().min().copied().unwrap_or(0);
+  let max = values.iter().flatten().max().copied().unwrap_or(0);
+  let range = (max - min).max(1);
+  let on_outline = |x: usize, y: usize| {
+    let (left, top) = (outline.x, outline.y);
+    let (right, bottom) = (left + outline.size - 1, top + outline.size - 1);
+    x >= left
+      && x <= right
+      && y >= top
+      && y <= bottom
+      && (x == left || x == right || y == top || y == bottom)
+  };
+
+  let mut res = format!("P2\n{} {}\n255\n", width, height);
+  for (y, row) in values.iter().enumerate() {
+    let shades = row
+      .iter()
+      .enumerate()
+      .map(|(x, value)| {
+        if on_outline(x + 1, y + 1) {
+          255
+        } else {
+          (value - min) * 254 / range
+        }
+      })
+      .map(|shade| shade.to_string())
+      .collect::<Vec<_>>();
+    res.push_str(&shades.join(" "));
+    res.push('\n');
   }
+  res
+}
 
-  grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Square {
+  x: usize,
+  y: usize,
+  size: usize,
+  power: i64,
+}
+impl Ord for Square {
+  fn cmp(&self, other: &Square) -> Ordering {
+    self
+      .power
+      .cmp(&other.power)
+      .then_with(|| other.size.cmp(&self.size))
+      .then_with(|| other.y.cmp(&self.y))
+      .then_with(|| other.x.cmp(&self.x))
+  }
+}
+impl PartialOrd for Square {
+  fn partial_cmp(&self, other: &Square) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
 }
 
 fn calculate_fuel_cell_power(x: usize, y: usize, grid_sn: usize) -> i32 {
@@ -116,4 +239,98 @@ mod test {
   fn part2_case2() {
     assert_eq!(find_best_fuel_square(&generate_grid(42)), (232, 251, 12));
   }
+
+  #[test]
+  fn square_power_test() {
+    let grid = generate_grid(18);
+    assert_eq!(grid.square_power(33, 45, 3), 29);
+    assert_eq!(grid.square_power(90, 269, 16), 113);
+    assert_eq!(
+      grid.square_power(7, 9, 1),
+      calculate_fuel_cell_power(7, 9, 18) as i64
+    );
+
+    let mut total = 0;
+    for y in 1..=GRID_SIZE {
+      for x in 1..=GRID_SIZE {
+        total += calculate_fuel_cell_power(x, y, 18) as i64;
+      }
+    }
+    assert_eq!(grid.square_power(1, 1, GRID_SIZE), total);
+  }
+
+  #[test]
+  fn top_squares_test() {
+    let grid = generate_grid(42);
+    let top = grid.top_squares(3..=3, 5);
+    assert_eq!(top.len(), 5);
+    assert_eq!((top[0].x, top[0].y, top[0].power), (21, 61, 30));
+    assert!(top.windows(2).all(|w| w[0].power >= w[1].power));
+
+    let top = grid.top_squares(10..=14, 3);
+    assert_eq!((top[0].x, top[0].y, top[0].size), (232, 251, 12));
+    assert!(top.iter().all(|s| s.size >= 10 && s.size <= 14));
+  }
+
+  #[test]
+  fn anchor_powers_test() {
+    let grid = PowerGrid::new(18, 6, 4);
+    let cells = grid.anchor_powers(1);
+    assert_eq!((cells.len(), cells[0].len()), (4, 6));
+    assert_eq!(cells[2][4], calculate_fuel_cell_power(5, 3, 18) as i64);
+
+    let anchors = grid.anchor_powers(3);
+    assert_eq!((anchors.len(), anchors[0].len()), (2, 4));
+    assert_eq!(anchors[1][2], grid.square_power(3, 2, 3));
+    assert!(grid.anchor_powers(5).is_empty());
+  }
+
+  #[test]
+  fn render_pgm_test() {
+    let values = vec![vec![0, 1, 2], vec![3, 4, 5], vec![6, 7, 8]];
+    let outline = Square {
+      x: 2,
+      y: 2,
+      size: 2,
+      power: 24,
+    };
+    assert_eq!(
+      render_pgm(&values, &outline),
+      "P2\n3 3\n255\n0 31 63\n95 255 255\n190 255 255\n"
+    );
+
+    let outline = Square {
+      x: 1,
+      y: 1,
+      size: 3,
+      power: 36,
+    };
+    let pgm = render_pgm(&values, &outline);
+    assert_eq!(pgm.lines().nth(4), Some("255 127 255"));
+  }
+
+  #[test]
+  fn custom_grid_size_test() {
+    let grid = PowerGrid::new(18, 40, 20);
+    let top = grid.top_squares(1..=100, 1);
+    assert!(top[0].size <= 20);
+    assert!(top[0].x + top[0].size - 1 <= 40);
+    assert!(top[0].y + top[0].size - 1 <= 20);
+
+    let mut best = i64::MIN;
+    for size in 1..=20 {
+      for y in 1..=20 - size + 1 {
+        for x in 1..=40 - size + 1 {
+          let mut sum = 0;
+          for j in 0..size {
+            for i in 0..size {
+              sum += calculate_fuel_cell_power(x + i, y + j, 18) as i64;
+            }
+          }
+          best = best.max(sum);
+        }
+      }
+    }
+    assert_eq!(top[0].power, best);
+  }
 }
@@ -1,125 +1,293 @@
-#![feature(map_first_last)]
 use regex::Regex;
-use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 
 fn main() {
-  let (pots, patterns) = read_input("input.txt");
+  let (pots, rules) = read_input("input.txt");
+  if rules.grows_from_nothing() {
+    println!(
+      "Warning: {} => # grows plants out of empty pots, the row is infinite",
+      ".".repeat(rules.width())
+    );
+  }
+  let missing = rules.missing_patterns();
+  if !missing.is_empty() {
+    println!(
+      "Missing rules, left without a plant:\n{}",
+      missing.join("\n")
+    );
+  }
+
   println!(
     "Day 12: Subterranean Sustainability part1 solution\n{:?}",
-    find_sum_after_20th_gen(&pots, &patterns)
+    find_sum_after_20th_gen(&pots, &rules)
   );
 
   println!(
     "Day 12: Subterranean Sustainability part2 solution\n{:?}",
-    find_sum_after_50_billion_iterations(&pots, &patterns)
+    find_sum_after_50_billion_iterations(&pots, &rules)
   );
 }
 
-fn find_sum_after_50_billion_iterations(
-  pots: &BTreeMap<i32, char>,
-  patterns: &BTreeMap<String, char>,
-) -> i64 {
+fn find_sum_after_50_billion_iterations(pots: &Pots, rules: &Rules) -> i64 {
+  find_sum_after_generations(pots, rules, 50_000_000_000)
+    .expect("The plants never settle into a repeating pattern")
+}
+
+const CYCLE_SEARCH_LIMIT: u64 = 100_000;
+
+// Exact sum of the plant positions after `generations`, following the row
+// through its cycle when there is one. None when the row keeps changing shape
+// past `CYCLE_SEARCH_LIMIT` generations and there are more to go than that,
+// or when the empty pots grow plants and the row never ends.
+fn find_sum_after_generations(
+  pots: &Pots,
+  rules: &Rules,
+  generations: u64,
+) -> Option<i64> {
+  if rules.grows_from_nothing() && generations > 0 {
+    return None;
+  }
+  let limit = generations.min(CYCLE_SEARCH_LIMIT);
+  let cycle = find_cycle(pots, rules, limit);
+  let (simulated, periods, drift) = match cycle {
+    Some(cycle) if generations > cycle.start => {
+      let after_start = generations - cycle.start;
+      (
+        cycle.start + after_start % cycle.period,
+        (after_start / cycle.period) as i64,
+        cycle.drift,
+      )
+    }
+    None if generations > limit => return None,
+    _ => (generations, 0, 0),
+  };
+
+  let curr_pots = advance(pots, rules, simulated);
+  Some(curr_pots.sum() + curr_pots.count() as i64 * periods * drift)
+}
+
+// The row repeats once its plants, trimmed of the empty pots around them,
+// form a pattern seen before. Every `period` generations from `start` on it
+// is the same pattern again, moved `drift` pots to the right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cycle {
+  start: u64,
+  period: u64,
+  drift: i64,
+}
+
+fn find_cycle(pots: &Pots, rules: &Rules, limit: u64) -> Option<Cycle> {
+  let mut seen = HashMap::new();
   let mut curr_pots = pots.clone();
-  let mut prev_sum = 0;
-  let mut prev_sum_diff = 0;
-  let mut sum = calculate_sum(pots);
-  let mut sum_diff = sum - prev_sum;
-  let mut total_sum = sum as i64;
-  let mut i = 0i64;
-  let mut repeat_num = 0;
-  while sum_diff != prev_sum_diff || repeat_num < 10 {
-    if sum_diff == prev_sum_diff {
-      repeat_num += 1;
-    } else {
-      repeat_num = 0;
+  for generation in 0..=limit {
+    let origin = curr_pots.origin;
+    if let Some((start, start_origin)) =
+      seen.insert(curr_pots.words.clone(), (generation, origin))
+    {
+      return Some(Cycle {
+        start,
+        period: generation - start,
+        drift: origin - start_origin,
+      });
     }
-    prev_sum = sum;
-    prev_sum_diff = sum_diff;
-    apply_transformations(&mut curr_pots, patterns);
-    sum = calculate_sum(&curr_pots);
-    sum_diff = sum - prev_sum;
-    total_sum += sum_diff as i64;
-    i += 1;
+    apply_transformations(&mut curr_pots, rules);
   }
+  None
+}
 
-  let remaining_iteration = 50_000_000_000i64 - i;
-  total_sum += remaining_iteration * sum_diff as i64;
-  total_sum
+fn find_sum_after_20th_gen(pots: &Pots, rules: &Rules) -> i64 {
+  find_sum_after_generations(pots, rules, 20)
+    .expect("The empty pots grow plants, the row is infinite")
 }
 
-fn find_sum_after_20th_gen(
-  pots: &BTreeMap<i32, char>,
-  patterns: &BTreeMap<String, char>,
-) -> i32 {
+fn advance(pots: &Pots, rules: &Rules, generations: u64) -> Pots {
   let mut curr_pots = pots.clone();
+  for _ in 0..generations {
+    apply_transformations(&mut curr_pots, rules);
+  }
+  curr_pots
+}
+
+// Slides a window of `rules.width()` bits over the row, from `radius` pots
+// left of the first plant to `radius` pots right of the last one, which is as
+// far as the plants can reach in a generation.
+fn apply_transformations(pots: &mut Pots, rules: &Rules) {
+  let radius = rules.radius as i64;
+  let from = pots.origin - radius;
+  let to = pots.origin + pots.len as i64 + radius;
+  let mask = rules.table.len() - 1;
+  let mut window = 0;
+  let next = (from..to).map(|pos| {
+    window = (window << 1 | pots.get(pos + radius) as usize) & mask;
+    rules.table[window]
+  });
+  *pots = Pots::from_bits(from, next);
+}
 
-  for _i in 0..20 {
-    apply_transformations(&mut curr_pots, patterns);
+// Plants as bits, bit `i` standing for the pot at `origin + i`. The row is
+// always trimmed to run from its first plant to its last one, so two rows
+// with the same `words` hold the same pattern, only moved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pots {
+  words: Vec<u64>,
+  len: usize,
+  origin: i64,
+}
+impl Pots {
+  pub fn from_bits(origin: i64, bits: impl Iterator<Item = bool>) -> Pots {
+    let mut pots = Pots {
+      words: Vec::new(),
+      len: 0,
+      origin: 0,
+    };
+    for (i, bit) in bits.enumerate() {
+      if !bit {
+        continue;
+      }
+      let pos = origin + i as i64;
+      if pots.len == 0 {
+        pots.origin = pos;
+      }
+      let index = (pos - pots.origin) as usize;
+      pots.words.resize(index / 64 + 1, 0);
+      pots.words[index / 64] |= 1 << (index % 64);
+      pots.len = index + 1;
+    }
+    pots
+  }
+
+  pub fn get(&self, pos: i64) -> bool {
+    let index = pos - self.origin;
+    if index < 0 || index >= self.len as i64 {
+      return false;
+    }
+    let index = index as usize;
+    self.words[index / 64] >> (index % 64) & 1 == 1
   }
 
-  calculate_sum(&curr_pots)
+  pub fn count(&self) -> u32 {
+    self.words.iter().map(|word| word.count_ones()).sum()
+  }
+
+  pub fn sum(&self) -> i64 {
+    let mut sum = 0;
+    for (i, &word) in self.words.iter().enumerate() {
+      let mut word = word;
+      while word != 0 {
+        let bit = word.trailing_zeros() as i64;
+        sum += self.origin + i as i64 * 64 + bit;
+        word &= word - 1;
+      }
+    }
+    sum
+  }
 }
 
-fn calculate_sum(pots: &BTreeMap<i32, char>) -> i32 {
-  pots.iter().fold(0, |acc, (pot_pos, pot_val)| {
-    if *pot_val == '#' {
-      return acc + pot_pos;
+// What each neighbourhood of `radius` pots either side grows into, indexed
+// by its pots read as bits with the leftmost one the most significant.
+// Patterns without a rule leave no plant, `defined` tells them apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rules {
+  radius: usize,
+  table: Vec<bool>,
+  defined: Vec<bool>,
+}
+impl Rules {
+  pub fn new(radius: usize) -> Rules {
+    let patterns = 1 << (2 * radius + 1);
+    Rules {
+      radius,
+      table: vec![false; patterns],
+      defined: vec![false; patterns],
     }
-    acc
-  })
-}
-
-fn apply_transformations(
-  pots: &mut BTreeMap<i32, char>,
-  patterns: &BTreeMap<String, char>,
-) {
-  let mut current_pots = pots.clone();
-  // expand the pots
-  let (first_pos, _) = current_pots.first_key_value().unwrap();
-  let mut first_plant_pos = *first_pos;
-  while current_pots.get(&first_plant_pos) == Some(&'.') {
-    first_plant_pos += 1;
-  }
-
-  let (last_pos, _) = current_pots.last_key_value().unwrap();
-  let mut last_plant_pos = *last_pos;
-  while current_pots.get(&last_plant_pos) == Some(&'.') {
-    last_plant_pos -= 1;
-  }
-  for i in 1..=5 {
-    pots.insert(first_plant_pos - i, '.');
-    pots.insert(last_pos + i, '.');
-  }
-
-  current_pots = pots.clone();
-  let keys = current_pots.keys();
-  for pos in keys {
-    let mut pot_range = String::new();
-    pot_range.push(*current_pots.get(&(pos - 2)).unwrap_or(&'.'));
-    pot_range.push(*current_pots.get(&(pos - 1)).unwrap_or(&'.'));
-    pot_range.push(*current_pots.get(&(pos)).unwrap());
-    pot_range.push(*current_pots.get(&(pos + 1)).unwrap_or(&'.'));
-    pot_range.push(*current_pots.get(&(pos + 2)).unwrap_or(&'.'));
-    if let Some(new_val) = patterns.get(&pot_range) {
-      pots.insert(*pos, *new_val);
-    } else {
-      pots.insert(*pos, '.');
+  }
+
+  pub fn width(&self) -> usize {
+    2 * self.radius + 1
+  }
+
+  pub fn set(&mut self, pattern: &str, plant: bool) {
+    let index = pattern_index(pattern);
+    self.table[index] = plant;
+    self.defined[index] = true;
+  }
+
+  pub fn grows_from_nothing(&self) -> bool {
+    self.table[0]
+  }
+
+  pub fn missing_patterns(&self) -> Vec<String> {
+    let width = self.width();
+    (0..self.table.len())
+      .filter(|&index| !self.defined[index])
+      .map(|index| {
+        (0..width)
+          .rev()
+          .map(|bit| if index >> bit & 1 == 1 { '#' } else { '.' })
+          .collect()
+      })
+      .collect()
+  }
+}
+
+fn pattern_index(pattern: &str) -> usize {
+  pattern
+    .chars()
+    .fold(0, |acc, ch| acc << 1 | (ch == '#') as usize)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RuleError {
+  // not a `<pots> => <pot>` line
+  Malformed { rule: String },
+  // a pattern without a middle pot
+  EvenWidth { rule: String },
+  // a pattern wider or narrower than the first rule's
+  WidthMismatch { rule: String, expected: usize },
+  // the same pattern again, growing something else
+  Conflict { rule: String },
+}
+
+// The radius comes from the first rule, every other one has to match it.
+fn parse_rules(rows: &[String]) -> Result<Rules, RuleError> {
+  let re = Regex::new(r"^([\.\#]+)\s=>\s([\.\#])$").unwrap();
+  let mut rules: Option<Rules> = None;
+  for row in rows.iter().filter(|row| !row.trim().is_empty()) {
+    let rule = row.to_string();
+    let caps = match re.captures(row.trim()) {
+      Some(caps) => caps,
+      None => return Err(RuleError::Malformed { rule }),
+    };
+    let width = caps[1].len();
+    if width % 2 == 0 {
+      return Err(RuleError::EvenWidth { rule });
+    }
+    let rules = rules.get_or_insert_with(|| Rules::new(width / 2));
+    if width != rules.width() {
+      let expected = rules.width();
+      return Err(RuleError::WidthMismatch { rule, expected });
     }
+
+    let plant = &caps[2] == "#";
+    let index = pattern_index(&caps[1]);
+    if rules.defined[index] && rules.table[index] != plant {
+      return Err(RuleError::Conflict { rule });
+    }
+    rules.set(&caps[1], plant);
   }
+
+  Ok(rules.unwrap_or_else(|| Rules::new(2)))
 }
 
-fn read_input(filename: &str) -> (BTreeMap<i32, char>, BTreeMap<String, char>) {
+fn read_input(filename: &str) -> (Pots, Rules) {
   let mut file = File::open(filename).expect("File not found");
   let mut content = String::new();
   file
     .read_to_string(&mut content)
     .expect("Failed to read input file");
 
-  let mut pots = BTreeMap::new();
-  let mut patterns = BTreeMap::new();
-
   let mut rows: Vec<String> = content
     .split_terminator('\n')
     .map(|s| s.to_string())
@@ -128,29 +296,234 @@ fn read_input(filename: &str) -> (BTreeMap<i32, char>, BTreeMap<String, char>) {
   let initial_state: Vec<&str> =
     initial_state_str.split_terminator(": ").collect();
   rows.remove(0);
-  let mut chars = initial_state[1].chars();
-  let mut i = 0;
-  while let Some(ch) = chars.next() {
-    pots.insert(i, ch);
-    i += 1;
-  }
-
-  let re = Regex::new(r"([\.\#]+)\s=>\s([\.\#])").unwrap();
-  for s in rows {
-    let caps = re.captures(&s).unwrap();
-    patterns.insert(caps[1].to_string(), caps[2].chars().next().unwrap());
-  }
+  let pots = Pots::from_bits(0, initial_state[1].chars().map(|ch| ch == '#'));
+  let rules = parse_rules(&rows)
+    .unwrap_or_else(|err| panic!("Invalid rule file: {:?}", err));
 
-  (pots, patterns)
+  (pots, rules)
 }
 
 #[cfg(test)]
 mod test {
   use super::*;
+  use std::collections::HashSet;
 
   #[test]
   fn part1_test() {
-    let (pots, patterns) = read_input("test-input.txt");
-    assert_eq!(find_sum_after_20th_gen(&pots, &patterns), 325);
+    let (pots, rules) = read_input("test-input.txt");
+    assert_eq!(find_sum_after_20th_gen(&pots, &rules), 325);
+  }
+
+  fn parse_pots(state: &str) -> Pots {
+    Pots::from_bits(0, state.chars().map(|ch| ch == '#'))
+  }
+
+  fn plant_rules(patterns: &[&str]) -> Rules {
+    let mut rules = Rules::new(patterns[0].len() / 2);
+    for pattern in patterns {
+      rules.set(pattern, true);
+    }
+    rules
+  }
+
+  #[test]
+  fn generations_test() {
+    let (pots, rules) = read_input("test-input.txt");
+    assert_eq!(find_sum_after_generations(&pots, &rules, 20), Some(325));
+    let cycle = find_cycle(&pots, &rules, 1000).unwrap();
+    assert_eq!(cycle.period, 1);
+    assert_eq!(cycle.drift, 1);
+
+    let curr_pots = advance(&pots, &rules, cycle.start + 50);
+    assert_eq!(
+      find_sum_after_generations(&pots, &rules, cycle.start + 50),
+      Some(curr_pots.sum())
+    );
+  }
+
+  #[test]
+  fn oscillator_test() {
+    // a lone plant grows into a pair that collapses into a lone plant two
+    // pots further, so the sum goes 0, 1, 2, 5, 4, 9, 6, ...
+    let pots = parse_pots("#");
+    let rules = plant_rules(&["..#..", ".#...", "##..."]);
+    assert_eq!(
+      find_cycle(&pots, &rules, 100),
+      Some(Cycle {
+        start: 0,
+        period: 2,
+        drift: 2
+      })
+    );
+    for generations in 0..10 {
+      let expected = if generations % 2 == 0 {
+        generations
+      } else {
+        2 * generations - 1
+      };
+      assert_eq!(
+        find_sum_after_generations(&pots, &rules, generations as u64),
+        Some(expected)
+      );
+    }
+    assert_eq!(
+      find_sum_after_50_billion_iterations(&pots, &rules),
+      50_000_000_000
+    );
+    assert_eq!(
+      find_sum_after_generations(&pots, &rules, 1_000_000_001),
+      Some(2_000_000_001)
+    );
+  }
+
+  #[test]
+  fn dying_row_test() {
+    let pots = parse_pots("#.#");
+    let rules = Rules::new(2);
+    assert_eq!(
+      find_cycle(&pots, &rules, 100),
+      Some(Cycle {
+        start: 1,
+        period: 1,
+        drift: 0
+      })
+    );
+    assert_eq!(find_sum_after_generations(&pots, &rules, 0), Some(2));
+    assert_eq!(find_sum_after_generations(&pots, &rules, 1 << 40), Some(0));
+  }
+
+  #[test]
+  fn pots_test() {
+    let pots = Pots::from_bits(-3, "..#.#....".chars().map(|ch| ch == '#'));
+    assert_eq!((pots.origin, pots.len, pots.count()), (-1, 3, 2));
+    assert_eq!(pots.sum(), 0);
+    assert!(pots.get(-1) && !pots.get(0) && pots.get(1) && !pots.get(2));
+
+    let wide = Pots::from_bits(10, (0..200).map(|i| i % 3 == 0 && i > 4));
+    assert_eq!((wide.origin, wide.len, wide.words.len()), (16, 193, 4));
+    assert_eq!(wide.sum(), (6..200).step_by(3).map(|i| i + 10).sum());
+    assert!(Pots::from_bits(7, vec![false; 5].into_iter())
+      .words
+      .is_empty());
+  }
+
+  // The pots as a plain set of positions, stepped the obvious way.
+  fn reference_step(plants: &HashSet<i64>, rules: &Rules) -> HashSet<i64> {
+    let min = plants.iter().min().copied().unwrap_or(0);
+    let max = plants.iter().max().copied().unwrap_or(0);
+    let radius = rules.radius as i64;
+    (min - radius..=max + radius)
+      .filter(|pos| {
+        let index = (pos - radius..=pos + radius)
+          .fold(0, |acc, p| acc << 1 | plants.contains(&p) as usize);
+        rules.table[index]
+      })
+      .collect()
+  }
+
+  #[test]
+  fn matches_reference_test() {
+    let mut seed = 12345u64;
+    let mut random = move || {
+      seed = seed
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+      seed >> 33
+    };
+    for case in 0..30 {
+      let mut rules = Rules::new(case % 3 + 1);
+      for index in 1..rules.table.len() {
+        rules.table[index] = random() % 2 == 0;
+      }
+      let state = (0..150)
+        .map(|_| if random() % 3 == 0 { '#' } else { '.' })
+        .collect::<String>();
+      let mut pots = parse_pots(&state);
+      let mut plants = state
+        .chars()
+        .enumerate()
+        .filter(|(_, ch)| *ch == '#')
+        .map(|(i, _)| i as i64)
+        .collect::<HashSet<_>>();
+      for _ in 0..100 {
+        apply_transformations(&mut pots, &rules);
+        plants = reference_step(&plants, &rules);
+        assert_eq!(pots.count() as usize, plants.len());
+        assert_eq!(pots.sum(), plants.iter().sum::<i64>());
+        assert!(plants.iter().all(|&pos| pots.get(pos)));
+      }
+    }
+  }
+
+  fn rows(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|line| line.to_string()).collect()
+  }
+
+  #[test]
+  fn parse_rules_test() {
+    let rules = parse_rules(&rows(&["..#.. => #", "##.## => .", ""])).unwrap();
+    assert_eq!((rules.radius, rules.width()), (2, 5));
+    assert_eq!(rules.missing_patterns().len(), 30);
+
+    let rules = parse_rules(&rows(&[".#. => #", "... => .", "#.# => #"]));
+    let rules = rules.unwrap();
+    assert_eq!(rules.radius, 1);
+    assert_eq!(
+      rules.missing_patterns(),
+      vec!["..#", ".##", "#..", "##.", "###"]
+    );
+
+    let (_, rules) = read_input("test-input.txt");
+    assert_eq!(rules.missing_patterns().len(), 32 - 14);
+    assert!(!rules.grows_from_nothing());
+  }
+
+  #[test]
+  fn invalid_rules_test() {
+    assert_eq!(
+      parse_rules(&rows(&["..#.. => #", "..#. => #"])),
+      Err(RuleError::EvenWidth {
+        rule: "..#. => #".to_string()
+      })
+    );
+    assert_eq!(
+      parse_rules(&rows(&["..#.. => #", "...#... => #"])),
+      Err(RuleError::WidthMismatch {
+        rule: "...#... => #".to_string(),
+        expected: 5
+      })
+    );
+    assert_eq!(
+      parse_rules(&rows(&["..#.. => #", "..#.. => ."])),
+      Err(RuleError::Conflict {
+        rule: "..#.. => .".to_string()
+      })
+    );
+    assert_eq!(
+      parse_rules(&rows(&["..x.. => #"])),
+      Err(RuleError::Malformed {
+        rule: "..x.. => #".to_string()
+      })
+    );
+  }
+
+  #[test]
+  fn radius_3_test() {
+    // a plant jumps three pots right each generation, seeing only itself at
+    // the far left of the window
+    let pots = parse_pots("#......#");
+    let rules = plant_rules(&["#......"]);
+    assert_eq!(rules.radius, 3);
+    assert_eq!(advance(&pots, &rules, 1).sum(), 3 + 10);
+    assert_eq!(find_sum_after_generations(&pots, &rules, 1000), Some(6007));
+  }
+
+  #[test]
+  fn grows_from_nothing_test() {
+    let pots = parse_pots("#");
+    let rules = plant_rules(&["....."]);
+    assert!(rules.grows_from_nothing());
+    assert_eq!(find_sum_after_generations(&pots, &rules, 0), Some(0));
+    assert_eq!(find_sum_after_generations(&pots, &rules, 5), None);
   }
 }
@@ -1,11 +1,20 @@
-#![feature(drain_filter)]
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::prelude::*;
 
 fn main() {
   let (map, carts) = read_input("input.txt");
+  let issues = validate_layout(&map, &carts);
+  for issue in issues.iter() {
+    println!("Layout issue: {:?}", issue);
+  }
+  if issues
+    .iter()
+    .any(|issue| matches!(issue, LayoutIssue::DanglingEnd { .. }))
+  {
+    return;
+  }
 
   println!(
     "Day 13: Mine Cart Madness part1 solution\n{:?}",
@@ -16,71 +25,113 @@ fn main() {
     "Day 13: Mine Cart Madness part2 solution\n{:?}",
     find_last_remaining_cart_pos(&map, &carts)
   );
+
+  if std::env::args().any(|arg| arg == "--collisions") {
+    let mut simulation = Simulation::new(&carts);
+    while simulation.carts().len() > 1 {
+      simulation.tick(&map);
+    }
+    for collision in simulation.collisions() {
+      println!(
+        "tick {}: cart {} ran into cart {} at {:?}",
+        collision.tick, collision.carts[0], collision.carts[1], collision.pos
+      );
+    }
+  }
 }
 
 fn find_last_remaining_cart_pos(
   map: &Vec<Vec<char>>,
   initial_carts: &Vec<Cart>,
 ) -> (usize, usize) {
-  let mut carts = initial_carts.clone();
-  carts.sort();
-
-  while carts.len() > 1 {
-    apply_tick_v2(map, &mut carts);
-  }
-  carts[0].pos
-}
-
-fn apply_tick_v2(map: &Vec<Vec<char>>, carts: &mut Vec<Cart>) {
-  let clone = carts.clone();
-  carts.clear();
-  let mut visited = HashSet::new();
-  for cart in clone.iter() {
-    visited.insert(cart.pos);
+  let mut simulation = Simulation::new(initial_carts);
+  while simulation.carts().len() > 1 {
+    simulation.tick(map);
   }
-  for cart in clone.iter() {
-    let mut cart_mut = *cart;
-    cart_mut.move_pos(map);
-    if visited.get(&cart_mut.pos).is_none() {
-      carts.push(cart_mut);
-      visited.insert(cart_mut.pos);
-    } else {
-      carts.drain_filter(|c| c.pos == cart_mut.pos);
-    }
-  }
-  carts.sort();
+  simulation.carts().first().expect("Every cart crashed").pos
 }
 
 fn find_first_collision(
   map: &Vec<Vec<char>>,
   initial_carts: &Vec<Cart>,
 ) -> (usize, usize) {
-  let mut carts = initial_carts.clone();
-  carts.sort();
-
+  let mut simulation = Simulation::new(initial_carts);
   loop {
-    let collisions = apply_tick(map, &mut carts);
-    if collisions.len() > 0 {
-      return collisions[0];
+    if let Some(collision) = simulation.tick(map).first() {
+      return collision.pos;
     }
   }
 }
 
-fn apply_tick(
-  map: &Vec<Vec<char>>,
-  carts: &mut Vec<Cart>,
-) -> Vec<(usize, usize)> {
-  for cart in carts.iter_mut() {
-    cart.move_pos(map);
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Collision {
+  tick: usize,
+  pos: (usize, usize),
+  // ids of the cart that moved into the other one and of the one it hit
+  carts: [usize; 2],
+}
+
+// Carts move one at a time in reading order, so a cart can run into one that
+// has not moved yet this tick, and crashed carts are gone before the next
+// cart moves.
+struct Simulation {
+  carts: Vec<Cart>,
+  tick: usize,
+  collisions: Vec<Collision>,
+}
+impl Simulation {
+  pub fn new(carts: &[Cart]) -> Simulation {
+    Simulation {
+      carts: carts.to_vec(),
+      tick: 0,
+      collisions: Vec::new(),
+    }
   }
-  carts.sort();
-  let mut collisions = Vec::new();
-  for i in 0..carts.len() - 1 {
-    if carts[i].pos == carts[i + 1].pos {
-      collisions.push(carts[i].pos);
+
+  pub fn carts(&self) -> &[Cart] {
+    &self.carts
+  }
+
+  pub fn collisions(&self) -> &[Collision] {
+    &self.collisions
+  }
+
+  // Runs one tick and returns the collisions it caused.
+  pub fn tick(&mut self, map: &Vec<Vec<char>>) -> &[Collision] {
+    self.tick += 1;
+    let first_collision = self.collisions.len();
+    self.carts.sort();
+    let mut occupied = self
+      .carts
+      .iter()
+      .enumerate()
+      .map(|(i, cart)| (cart.pos, i))
+      .collect::<HashMap<_, _>>();
+    let mut crashed = vec![false; self.carts.len()];
+    for i in 0..self.carts.len() {
+      if crashed[i] {
+        continue;
+      }
+      occupied.remove(&self.carts[i].pos);
+      self.carts[i].move_pos(map);
+      let pos = self.carts[i].pos;
+      if let Some(other) = occupied.remove(&pos) {
+        crashed[i] = true;
+        crashed[other] = true;
+        self.collisions.push(Collision {
+          tick: self.tick,
+          pos,
+          carts: [self.carts[i].id, self.carts[other].id],
+        });
+      } else {
+        occupied.insert(pos, i);
+      }
     }
+
+    let mut crashed = crashed.into_iter();
+    self.carts.retain(|_| !crashed.next().unwrap());
+    &self.collisions[first_collision..]
   }
-  collisions
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -90,16 +141,241 @@ enum Directions {
   LEFT,
   RIGHT,
 }
+impl Directions {
+  pub fn all() -> [Directions; 4] {
+    [
+      Directions::UP,
+      Directions::DOWN,
+      Directions::LEFT,
+      Directions::RIGHT,
+    ]
+  }
+
+  pub fn opposite(self) -> Directions {
+    match self {
+      Directions::UP => Directions::DOWN,
+      Directions::DOWN => Directions::UP,
+      Directions::LEFT => Directions::RIGHT,
+      Directions::RIGHT => Directions::LEFT,
+    }
+  }
+
+  // The neighbouring position, None past the top or left edge.
+  pub fn step(self, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+    match self {
+      Directions::UP => y.checked_sub(1).map(|y| (x, y)),
+      Directions::DOWN => Some((x, y + 1)),
+      Directions::LEFT => x.checked_sub(1).map(|x| (x, y)),
+      Directions::RIGHT => Some((x + 1, y)),
+    }
+  }
+
+  fn bit(self) -> u8 {
+    match self {
+      Directions::UP => 1,
+      Directions::DOWN => 2,
+      Directions::LEFT => 4,
+      Directions::RIGHT => 8,
+    }
+  }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum LayoutIssue {
+  // track leading from `pos` towards `dir` into nothing that leads back
+  DanglingEnd {
+    pos: (usize, usize),
+    dir: Directions,
+  },
+  // the track around a cart does not fit the straight piece assumed under it
+  AmbiguousCart {
+    pos: (usize, usize),
+    cart: usize,
+  },
+  // a piece of track no cart starts on, `pos` being its first cell in
+  // reading order
+  UnreachableLoop {
+    pos: (usize, usize),
+    cells: usize,
+  },
+}
+
+// Ways a piece can be connected, as sets of direction bits. Curves can be
+// either corner of a loop, which only their neighbours tell apart.
+fn piece_connections(piece: char) -> Vec<u8> {
+  let (up, down, left, right) = (1, 2, 4, 8);
+  match piece {
+    '|' => vec![up | down],
+    '-' => vec![left | right],
+    '+' => vec![up | down | left | right],
+    '/' => vec![down | right, up | left],
+    '\\' => vec![down | left, up | right],
+    _ => Vec::new(),
+  }
+}
+
+// Rebuilds the track graph from the map, treating everything it can't read
+// as blank, and reports every spot a cart could run off or get lost on.
+fn validate_layout(map: &[Vec<char>], carts: &[Cart]) -> Vec<LayoutIssue> {
+  let width = map.len();
+  let height = map.iter().map(|column| column.len()).max().unwrap_or(0);
+  let cart_at = carts.iter().map(|cart| cart.pos).collect::<HashSet<_>>();
+  let piece = |(x, y): (usize, usize)| {
+    map
+      .get(x)
+      .and_then(|column| column.get(y))
+      .copied()
+      .unwrap_or(' ')
+  };
+  let neighbour = |pos, dir: Directions| {
+    dir.step(pos).filter(|&(x, y)| x < width && y < height)
+  };
+
+  // what each cell could connect to, anything at all under a cart
+  let mut possible = vec![vec![0u8; height]; width];
+  for (x, column) in possible.iter_mut().enumerate() {
+    for (y, cell) in column.iter_mut().enumerate() {
+      *cell = if cart_at.contains(&(x, y)) {
+        0b1111
+      } else {
+        piece_connections(piece((x, y)))
+          .iter()
+          .fold(0, |acc, c| acc | c)
+      };
+    }
+  }
+  let leads_back = |pos, dir: Directions| match neighbour(pos, dir) {
+    Some((x, y)) => possible[x][y] & dir.opposite().bit() != 0,
+    None => false,
+  };
+
+  let mut issues = Vec::new();
+  let mut connections = vec![vec![0u8; height]; width];
+  for (x, column) in connections.iter_mut().enumerate() {
+    for (y, cell) in column.iter_mut().enumerate() {
+      let pos = (x, y);
+      let matching = |option: u8| {
+        Directions::all()
+          .iter()
+          .filter(|dir| option & dir.bit() != 0 && leads_back(pos, **dir))
+          .count()
+      };
+      *cell = if cart_at.contains(&pos) {
+        0
+      } else {
+        let options = piece_connections(piece(pos));
+        let fitting = options
+          .iter()
+          .filter(|&&option| matching(option) == option.count_ones() as usize)
+          .fold(0, |acc, option| acc | option);
+        if fitting != 0 {
+          fitting
+        } else {
+          options
+            .into_iter()
+            .max_by_key(|&o| matching(o))
+            .unwrap_or(0)
+        }
+      };
+    }
+  }
+
+  // with the track around them settled, carts sit on whatever leads to them
+  let mut carts = carts.to_vec();
+  carts.sort();
+  for cart in carts.iter() {
+    let pos = cart.pos;
+    let around = Directions::all()
+      .iter()
+      .filter(|&&dir| match neighbour(pos, dir) {
+        Some((x, y)) => connections[x][y] & dir.opposite().bit() != 0,
+        None => false,
+      })
+      .fold(0, |acc, dir| acc | dir.bit());
+    if piece_connections(piece(pos)) != vec![around] {
+      let cart = cart.id;
+      issues.push(LayoutIssue::AmbiguousCart { pos, cart });
+    }
+    connections[pos.0][pos.1] = around;
+  }
+
+  let connected = |pos, dir: Directions| match neighbour(pos, dir) {
+    Some((x, y)) => connections[x][y] & dir.opposite().bit() != 0,
+    None => false,
+  };
+  // both passes below go column by column, so their issues get put back in
+  // reading order at the end
+  let mut dangling = Vec::new();
+  for (x, column) in connections.iter().enumerate() {
+    for (y, &cell) in column.iter().enumerate() {
+      for &dir in Directions::all().iter() {
+        if cell & dir.bit() != 0 && !connected((x, y), dir) {
+          dangling.push(LayoutIssue::DanglingEnd { pos: (x, y), dir });
+        }
+      }
+    }
+  }
+
+  let mut unreachable = Vec::new();
+  let mut seen = vec![vec![false; height]; width];
+  for (x, column) in connections.iter().enumerate() {
+    for (y, &cell) in column.iter().enumerate() {
+      if seen[x][y] || cell == 0 {
+        continue;
+      }
+      seen[x][y] = true;
+      let mut stack = vec![(x, y)];
+      let mut first = (x, y);
+      let mut cells = 0;
+      let mut has_cart = false;
+      while let Some(pos) = stack.pop() {
+        cells += 1;
+        has_cart |= cart_at.contains(&pos);
+        if (pos.1, pos.0) < (first.1, first.0) {
+          first = pos;
+        }
+        for &dir in Directions::all().iter() {
+          if connections[pos.0][pos.1] & dir.bit() == 0 || !connected(pos, dir)
+          {
+            continue;
+          }
+          let (next_x, next_y) = neighbour(pos, dir).unwrap();
+          if !seen[next_x][next_y] {
+            seen[next_x][next_y] = true;
+            stack.push((next_x, next_y));
+          }
+        }
+      }
+      if !has_cart {
+        unreachable.push(LayoutIssue::UnreachableLoop { pos: first, cells });
+      }
+    }
+  }
+
+  for mut found in [dangling, unreachable] {
+    found.sort_by_key(|issue| match *issue {
+      LayoutIssue::DanglingEnd { pos, .. }
+      | LayoutIssue::AmbiguousCart { pos, .. }
+      | LayoutIssue::UnreachableLoop { pos, .. } => (pos.1, pos.0),
+    });
+    issues.extend(found);
+  }
+
+  issues
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 struct Cart {
+  // index in reading order at the start, kept for the collision log
+  id: usize,
   pos: (usize, usize),
   dir: Directions,
   intersection_count: usize,
 }
 impl Cart {
-  pub fn new(pos: (usize, usize), dir: Directions) -> Self {
+  pub fn new(id: usize, pos: (usize, usize), dir: Directions) -> Self {
     Self {
+      id,
       pos,
       dir,
       intersection_count: 0,
@@ -177,6 +453,10 @@ fn read_input(filename: &str) -> (Vec<Vec<char>>, Vec<Cart>) {
     .read_to_string(&mut content)
     .expect("Failed to read input file");
 
+  parse_input(&content)
+}
+
+fn parse_input(content: &str) -> (Vec<Vec<char>>, Vec<Cart>) {
   let mut carts = Vec::new();
   let map = content
     .split_terminator('\n')
@@ -203,7 +483,7 @@ fn read_input(filename: &str) -> (Vec<Vec<char>>, Vec<Cart>) {
               map_value = '|';
             }
 
-            carts.push(Cart::new((i, acc[i].len()), dir));
+            carts.push(Cart::new(carts.len(), (i, acc[i].len()), dir));
             acc[i].push(map_value);
           }
           _ => acc[i].push(ch),
@@ -233,4 +513,103 @@ mod test {
 
     assert_eq!(find_last_remaining_cart_pos(&map, &carts), (6, 4));
   }
+
+  #[test]
+  fn collision_log_test() {
+    let (map, carts) = read_input("test-input2.txt");
+    let mut simulation = Simulation::new(&carts);
+    while simulation.carts().len() > 1 {
+      simulation.tick(&map);
+    }
+    let log = simulation
+      .collisions()
+      .iter()
+      .map(|c| (c.tick, c.pos))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      log,
+      vec![(1, (2, 0)), (1, (2, 4)), (1, (6, 4)), (3, (2, 4))]
+    );
+    assert_eq!(simulation.collisions()[0].carts, [1, 0]);
+  }
+
+  #[test]
+  fn swap_test() {
+    // the carts would trade places within the tick if they both moved at
+    // once, but the first one runs into the second before it gets to move
+    let (map, carts) = parse_input("/-><-\\\n\\----/\n");
+    let mut simulation = Simulation::new(&carts);
+    assert_eq!(
+      simulation.tick(&map),
+      &[Collision {
+        tick: 1,
+        pos: (3, 0),
+        carts: [0, 1]
+      }]
+    );
+    assert!(simulation.carts().is_empty());
+  }
+
+  #[test]
+  fn train_test() {
+    // the front cart leaves its spot before the one behind takes it
+    let (map, carts) = parse_input("/<<-\\\n|   |\n\\---/\n");
+    let mut simulation = Simulation::new(&carts);
+    assert!(simulation.tick(&map).is_empty());
+    let positions = simulation.carts().iter().map(|c| c.pos);
+    assert_eq!(positions.collect::<Vec<_>>(), vec![(0, 0), (1, 0)]);
+  }
+
+  #[test]
+  fn valid_layout_test() {
+    for input in ["test-input.txt", "test-input2.txt"].iter() {
+      let (map, carts) = read_input(input);
+      assert_eq!(validate_layout(&map, &carts), vec![]);
+    }
+  }
+
+  #[test]
+  fn dangling_end_test() {
+    let (map, carts) = parse_input("/->-\\\n|   |\n\\-- /\n");
+    assert_eq!(
+      validate_layout(&map, &carts),
+      vec![
+        LayoutIssue::DanglingEnd {
+          pos: (2, 2),
+          dir: Directions::RIGHT
+        },
+        LayoutIssue::DanglingEnd {
+          pos: (4, 2),
+          dir: Directions::LEFT
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn ambiguous_cart_test() {
+    // the cart sits where the loops cross, which read_input takes for a
+    // straight piece
+    let (map, carts) =
+      parse_input("  /-\\  \n/->-+-\\\n| | | |\n\\-+-+-/\n  \\-/  \n");
+    assert_eq!(
+      validate_layout(&map, &carts),
+      vec![LayoutIssue::AmbiguousCart {
+        pos: (2, 1),
+        cart: 0
+      }]
+    );
+  }
+
+  #[test]
+  fn unreachable_loop_test() {
+    let (map, carts) = parse_input("/>\\ /\\\n\\-/ \\/\n");
+    assert_eq!(
+      validate_layout(&map, &carts),
+      vec![LayoutIssue::UnreachableLoop {
+        pos: (4, 0),
+        cells: 4
+      }]
+    );
+  }
 }
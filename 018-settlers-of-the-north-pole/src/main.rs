@@ -1,58 +1,52 @@
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::prelude::*;
 
 fn main() {
   let map = read_input("input.txt");
+  let cycle = find_cycle(&map, 1_000_000_000);
   println!(
     "Day 18: Settlers of The North Pole part1 solution\n{:?}",
-    calculate_total_resource_value(&map, 10)
+    calculate_total_resource_value(&map, 10, cycle)
   );
 
   println!(
     "Day 18: Settlers of The North Pole part2 solution\n{:?}",
-    calculate_total_resource_value(&map, 1_000_000_000)
+    calculate_total_resource_value(&map, 1_000_000_000, cycle)
   );
+
+  if let Some(cycle) = cycle {
+    println!(
+      "The area repeats every {} minutes from minute {}",
+      cycle.length, cycle.start
+    );
+  }
 }
 
+// `cycle` is what `find_cycle` found for `map`, if anything, so the minutes
+// past its start can be skipped.
 fn calculate_total_resource_value(
   map: &Vec<Vec<char>>,
   iterations: usize,
+  cycle: Option<Cycle>,
 ) -> usize {
-  let mut previous_states = HashMap::new();
-  previous_states.insert(map.clone(), 0);
-  let mut curr_map = map.clone();
-  let mut minutes = 1;
-  let mut repeat_min = 0;
-  while minutes <= iterations {
-    curr_map = apply_transformation(&curr_map);
-    if let Some(rep_min) = previous_states.get(&curr_map) {
-      repeat_min = *rep_min;
-      break;
-    }
-    previous_states.insert(curr_map.clone(), minutes);
-    minutes += 1;
-  }
-
-  if minutes < iterations {
-    let cycle = minutes - repeat_min;
-    let remaining = (iterations - minutes) % cycle;
-    for (m, p) in previous_states.iter() {
-      if *p == repeat_min + remaining {
-        curr_map = m.clone();
-        break;
-      }
+  let minutes = match cycle {
+    Some(cycle) if iterations > cycle.start => {
+      cycle.start + (iterations - cycle.start) % cycle.length
     }
-  }
+    _ => iterations,
+  };
+  let curr_map = advance(map, minutes);
 
   let mut count = (0, 0, 0);
-  for j in 0..curr_map[0].len() {
-    for i in 0..curr_map.len() {
-      match curr_map[i][j] {
+  for row in curr_map.iter() {
+    for ch in row.iter() {
+      match ch {
         '.' => count.0 += 1,
         '|' => count.1 += 1,
         '#' => count.2 += 1,
-        _ => panic!("unexpected char {}", map[i][j]),
+        _ => panic!("unexpected char {}", ch),
       }
     }
   }
@@ -60,6 +54,63 @@ fn calculate_total_resource_value(
   count.1 * count.2
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cycle {
+  start: usize,
+  length: usize,
+}
+
+// Brent's cycle detection, keeping only the tortoise and hare maps and their
+// hashes. Gives up with None once the hare gets past `limit` minutes, since
+// at that point the plain simulation is already done.
+fn find_cycle(map: &Vec<Vec<char>>, limit: usize) -> Option<Cycle> {
+  let mut power = 1;
+  let mut length = 1;
+  let mut tortoise = map.clone();
+  let mut tortoise_hash = hash_map(&tortoise);
+  let mut hare = apply_transformation(map);
+  let mut hare_minute = 1;
+  while hash_map(&hare) != tortoise_hash || hare != tortoise {
+    if hare_minute > limit {
+      return None;
+    }
+    if power == length {
+      tortoise = hare.clone();
+      tortoise_hash = hash_map(&tortoise);
+      power *= 2;
+      length = 0;
+    }
+    hare = apply_transformation(&hare);
+    hare_minute += 1;
+    length += 1;
+  }
+
+  let mut tortoise = map.clone();
+  let mut hare = advance(map, length);
+  let mut start = 0;
+  while hash_map(&hare) != hash_map(&tortoise) || hare != tortoise {
+    tortoise = apply_transformation(&tortoise);
+    hare = apply_transformation(&hare);
+    start += 1;
+  }
+
+  Some(Cycle { start, length })
+}
+
+fn hash_map(map: &Vec<Vec<char>>) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  map.hash(&mut hasher);
+  hasher.finish()
+}
+
+fn advance(map: &[Vec<char>], minutes: usize) -> Vec<Vec<char>> {
+  let mut curr_map = map.to_vec();
+  for _ in 0..minutes {
+    curr_map = apply_transformation(&curr_map);
+  }
+  curr_map
+}
+
 fn apply_transformation(map: &Vec<Vec<char>>) -> Vec<Vec<char>> {
   let mut res = map.clone();
   for j in 0..res[0].len() {
@@ -131,12 +182,35 @@ mod test {
   #[test]
   fn part1_test() {
     let map = read_input("test-input.txt");
-    assert_eq!(calculate_total_resource_value(&map, 10), 1147);
+    let cycle = find_cycle(&map, 10);
+    assert_eq!(calculate_total_resource_value(&map, 10, cycle), 1147);
+    assert_eq!(calculate_total_resource_value(&map, 10, None), 1147);
   }
 
   #[test]
   fn part2_test() {
     let map = read_input("test-input.txt");
-    assert_eq!(calculate_total_resource_value(&map, 1_000_000_000), 1147);
+    // the example has no lumberyards or trees left from minute 18 on
+    let cycle = find_cycle(&map, 1_000_000_000);
+    assert_eq!(
+      calculate_total_resource_value(&map, 1_000_000_000, cycle),
+      0
+    );
+  }
+
+  #[test]
+  fn find_cycle_test() {
+    let map = read_input("test-input.txt");
+    let cycle = find_cycle(&map, 1_000_000_000).unwrap();
+    assert_eq!(cycle.length, 1);
+    assert_eq!(
+      advance(&map, cycle.start),
+      advance(&map, cycle.start + cycle.length)
+    );
+    assert_ne!(
+      advance(&map, cycle.start - 1),
+      advance(&map, cycle.start - 1 + cycle.length)
+    );
+    assert_eq!(find_cycle(&map, 5), None);
   }
 }
@@ -8,80 +8,145 @@ fn main() {
 
   let depth = 4848;
   let target = (15, 700);
-  let mut map = build_map(depth, &target);
+  let rules = Rules::default();
+  let map = build_map(depth, &target);
   println!(
     "Day 22: Mode Maze part1 solution\n{:?}",
     calculate_risk_level(&map, &target)
   );
 
-  println!(
-    "Day 22: Mode Maze part2 solution\n{:?}",
-    find_fastest_way(&mut map, depth, &target)
-  );
+  let route = find_fastest_way(&map, &rules);
+  println!("Day 22: Mode Maze part2 solution\n{:?}", route.time);
+  if std::env::args().any(|arg| arg == "--render") {
+    print!("{}", render_route(&map, &rules, &route));
+  }
 }
 
-fn find_fastest_way(
-  map: &mut HashMap<(i32, i32), FieldInfo>,
-  depth: i32,
-  target: &(i32, i32),
-) -> i32 {
-  let mut heap = BinaryHeap::new();
-  heap.push(State::new((0, 0), Tool::Torch, 0));
-  let mut visited = HashMap::new();
-  while let Some(State { time, pos, tool }) = heap.pop() {
-    let field_type;
-    if let Some(info) = map.get(&pos) {
-      field_type = info.r#type;
+fn find_fastest_way(map: &Cave, rules: &Rules) -> Route {
+  find_fastest_ways(map, rules, 1)
+    .into_iter()
+    .next()
+    .expect("No route to the target within the cave bounds")
+}
+
+// A* over (position, tool) states. The heuristic is the Manhattan distance to
+// the target plus one switch if we aren't holding the target tool yet, which
+// never overestimates, so the first K arrivals at the target are the K best
+// routes.
+fn find_fastest_ways(map: &Cave, rules: &Rules, k: usize) -> Vec<Route> {
+  let target = map.target;
+  rules.validate();
+  let tool_count = rules.tools.len();
+  let estimate = |pos: (i32, i32), tool: Tool| {
+    let distance = (target.0 - pos.0).abs() + (target.1 - pos.1).abs();
+    let switch = if tool == rules.target_tool {
+      0
     } else {
-      let field_info = FieldInfo::new(map, target, pos, depth);
-      field_type = field_info.r#type;
-    }
-    if !get_eligible_tools(field_type).contains(&tool) {
+      rules.switch_cost
+    };
+    distance * rules.move_cost + switch
+  };
+  let mut heap = BinaryHeap::new();
+  heap.push(State::new(
+    (0, 0),
+    rules.start_tool,
+    0,
+    estimate((0, 0), rules.start_tool),
+    None,
+  ));
+  let mut labels: Vec<State> = Vec::new();
+  let mut settled = vec![0; map.fields.len() * tool_count];
+  let mut routes = Vec::new();
+  while let Some(state) = heap.pop() {
+    let State {
+      time, pos, tool, ..
+    } = state;
+    let field_type = map.get(pos).unwrap().r#type;
+    if !rules.eligible_tools(field_type).contains(&tool) {
       continue;
     }
 
-    if let Some(visited_minutes) = visited.get(&(pos, tool)) {
-      if *visited_minutes <= time {
-        continue;
-      }
+    let settled_count = &mut settled[map.index(pos) * tool_count + tool.0];
+    if *settled_count >= k {
+      continue;
     }
-    visited.insert((pos, tool), time);
-    if &pos == target && tool == Tool::Torch {
-      return time;
+    *settled_count += 1;
+    labels.push(state);
+    let label = labels.len() - 1;
+    if pos == target && tool == rules.target_tool {
+      routes.push(Route::from_labels(&labels, label));
+      if routes.len() == k {
+        break;
+      }
+      continue;
     }
 
-    for (dx, dy) in vec![(1, 0), (-1, 0), (0, 1), (0, -1)] {
-      let new_x = pos.0 + dx;
-      let new_y = pos.1 + dy;
-      if new_x >= 0 && new_y >= 0 {
-        heap.push(State::new((new_x, new_y), tool, time + 1));
+    for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+      let new_pos = (pos.0 + dx, pos.1 + dy);
+      if map.get(new_pos).is_some() {
+        heap.push(State::new(
+          new_pos,
+          tool,
+          time + rules.move_cost,
+          time + rules.move_cost + estimate(new_pos, tool),
+          Some(label),
+        ));
       }
     }
-    for t in vec![Tool::ClimbingGear, Tool::Torch, Tool::Neither] {
+    for t in (0..tool_count).map(Tool) {
       if tool != t {
-        heap.push(State::new(pos, t, time + 7));
+        heap.push(State::new(
+          pos,
+          t,
+          time + rules.switch_cost,
+          time + rules.switch_cost + estimate(pos, t),
+          Some(label),
+        ));
       }
     }
   }
-  unreachable!()
+  routes
 }
 
-fn get_eligible_tools(field_type: char) -> Vec<Tool> {
-  match field_type {
-    '.' => vec![Tool::ClimbingGear, Tool::Torch],
-    '=' => vec![Tool::ClimbingGear, Tool::Neither],
-    _ => vec![Tool::Torch, Tool::Neither],
+fn render_route(map: &Cave, rules: &Rules, route: &Route) -> String {
+  let mut path = HashMap::new();
+  let (mut pos, mut tool) = ((0, 0), rules.start_tool);
+  let (mut max_x, mut max_y) = map.target;
+  path.insert(pos, tool);
+  for step in route.steps.iter() {
+    match step.action {
+      Action::Move(next) => pos = next,
+      Action::Switch(next) => tool = next,
+    }
+    path.insert(pos, tool);
+    max_x = max_x.max(pos.0);
+    max_y = max_y.max(pos.1);
   }
+
+  let mut res = String::new();
+  for y in 0..=max_y {
+    for x in 0..=max_x {
+      let ch = if (x, y) == (0, 0) {
+        'M'
+      } else if (x, y) == map.target {
+        'T'
+      } else if let Some(tool) = path.get(&(x, y)) {
+        rules.tools[tool.0]
+      } else {
+        map.get((x, y)).unwrap().r#type
+      };
+      res.push(ch);
+    }
+    res.push('\n');
+  }
+  res
 }
 
-fn calculate_risk_level(
-  map: &HashMap<(i32, i32), FieldInfo>,
-  target: &(i32, i32),
-) -> i32 {
+fn calculate_risk_level(map: &Cave, target: &(i32, i32)) -> i32 {
   let mut risk_level = 0;
   for y in 0..=target.1 {
     for x in 0..=target.0 {
-      let field_info = map.get(&(x, y)).unwrap();
+      let field_info = map.get((x, y)).unwrap();
       risk_level += match field_info.r#type {
         '.' => 0,
         '=' => 1,
@@ -92,34 +157,135 @@ fn calculate_risk_level(
   risk_level
 }
 
-fn build_map(
+const SEARCH_PADDING: i32 = 100;
+
+fn build_map(depth: i32, target: &(i32, i32)) -> Cave {
+  Cave::new(
+    depth,
+    target,
+    (target.0 + SEARCH_PADDING, target.1 + SEARCH_PADDING),
+    &Rules::default(),
+  )
+}
+
+#[derive(Clone, Debug)]
+struct Cave {
   depth: i32,
-  target: &(i32, i32),
-) -> HashMap<(i32, i32), FieldInfo> {
-  let mut map = HashMap::new();
-  FieldInfo::new(&mut map, target, (0, 0), depth);
-  for y in 0..=target.1 {
-    for x in 0..=target.0 {
-      FieldInfo::new(&mut map, target, (x, y), depth);
+  target: (i32, i32),
+  region_types: Vec<char>,
+  width: i32,
+  height: i32,
+  fields: Vec<FieldInfo>,
+}
+impl Cave {
+  pub fn new(
+    depth: i32,
+    target: &(i32, i32),
+    bounds: (i32, i32),
+    rules: &Rules,
+  ) -> Self {
+    if target.0 > bounds.0 || target.1 > bounds.1 {
+      panic!("target {:?} is outside of bounds {:?}", target, bounds);
+    }
+    let mut cave = Self {
+      depth,
+      target: *target,
+      region_types: rules.region_types.clone(),
+      width: bounds.0 + 1,
+      height: bounds.1 + 1,
+      fields: Vec::with_capacity(((bounds.0 + 1) * (bounds.1 + 1)) as usize),
+    };
+    for y in 0..cave.height {
+      for x in 0..cave.width {
+        let field = FieldInfo::new(&cave, (x, y));
+        cave.fields.push(field);
+      }
+    }
+    cave
+  }
+
+  fn index(&self, pos: (i32, i32)) -> usize {
+    (pos.1 * self.width + pos.0) as usize
+  }
+
+  pub fn get(&self, pos: (i32, i32)) -> Option<&FieldInfo> {
+    if pos.0 < 0 || pos.1 < 0 || pos.0 >= self.width || pos.1 >= self.height {
+      return None;
+    }
+    self.fields.get(self.index(pos))
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+  Move((i32, i32)),
+  Switch(Tool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Step {
+  time: i32,
+  action: Action,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Route {
+  time: i32,
+  steps: Vec<Step>,
+}
+impl Route {
+  fn from_labels(labels: &[State], last: usize) -> Self {
+    let mut steps = Vec::new();
+    let mut curr = &labels[last];
+    while let Some(parent) = curr.parent {
+      let prev = &labels[parent];
+      let action = if prev.pos != curr.pos {
+        Action::Move(curr.pos)
+      } else {
+        Action::Switch(curr.tool)
+      };
+      steps.push(Step {
+        time: curr.time,
+        action,
+      });
+      curr = prev;
+    }
+    steps.reverse();
+    Self {
+      time: labels[last].time,
+      steps,
     }
   }
-  map
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct State {
   time: i32,
+  estimate: i32,
   pos: (i32, i32),
   tool: Tool,
+  parent: Option<usize>,
 }
 impl State {
-  pub fn new(pos: (i32, i32), tool: Tool, time: i32) -> Self {
-    Self { pos, time, tool }
+  pub fn new(
+    pos: (i32, i32),
+    tool: Tool,
+    time: i32,
+    estimate: i32,
+    parent: Option<usize>,
+  ) -> Self {
+    Self {
+      pos,
+      time,
+      estimate,
+      tool,
+      parent,
+    }
   }
 }
 impl Ord for State {
   fn cmp(&self, other: &State) -> Ordering {
-    (other.time).cmp(&(self.time))
+    (other.estimate).cmp(&(self.estimate))
   }
 }
 impl PartialOrd for State {
@@ -129,10 +295,67 @@ impl PartialOrd for State {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-enum Tool {
-  Torch,
-  ClimbingGear,
-  Neither,
+struct Tool(usize);
+impl Tool {
+  const TORCH: Tool = Tool(0);
+  const CLIMBING_GEAR: Tool = Tool(1);
+  const NEITHER: Tool = Tool(2);
+}
+
+#[derive(Clone, Debug)]
+struct Rules {
+  move_cost: i32,
+  switch_cost: i32,
+  // region type for each remainder of the erosion level
+  region_types: Vec<char>,
+  // every tool there is, by the symbol used when rendering routes, `Tool(i)`
+  // being the i-th of them
+  tools: Vec<char>,
+  eligible_tools: HashMap<char, Vec<Tool>>,
+  start_tool: Tool,
+  target_tool: Tool,
+}
+impl Rules {
+  pub fn eligible_tools(&self, region_type: char) -> &[Tool] {
+    self
+      .eligible_tools
+      .get(&region_type)
+      .map_or(&[], |tools| tools.as_slice())
+  }
+
+  // Panics on a tool that isn't in `tools`, which the search would otherwise
+  // never switch to or keep its counts for.
+  pub fn validate(&self) {
+    let eligible = self.eligible_tools.values().flatten();
+    let referenced = eligible.chain([&self.start_tool, &self.target_tool]);
+    for tool in referenced {
+      if tool.0 >= self.tools.len() {
+        panic!(
+          "tool {} is not one of the {} tools {:?}",
+          tool.0,
+          self.tools.len(),
+          self.tools
+        );
+      }
+    }
+  }
+}
+impl Default for Rules {
+  fn default() -> Self {
+    let mut eligible_tools = HashMap::new();
+    eligible_tools.insert('.', vec![Tool::CLIMBING_GEAR, Tool::TORCH]);
+    eligible_tools.insert('=', vec![Tool::CLIMBING_GEAR, Tool::NEITHER]);
+    eligible_tools.insert('|', vec![Tool::TORCH, Tool::NEITHER]);
+    Self {
+      move_cost: 1,
+      switch_cost: 7,
+      region_types: vec!['.', '=', '|'],
+      tools: vec!['t', 'c', 'n'],
+      eligible_tools,
+      start_tool: Tool::TORCH,
+      target_tool: Tool::TORCH,
+    }
+  }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -141,48 +364,27 @@ struct FieldInfo {
   r#type: char,
 }
 impl FieldInfo {
-  pub fn new(
-    map: &mut HashMap<(i32, i32), FieldInfo>,
-    target: &(i32, i32),
-    pos: (i32, i32),
-    depth: i32,
-  ) -> Self {
-    let geo_index = if pos == *target || pos == (0, 0) {
+  // Expects the fields to the left and above `pos` to already be in the cave.
+  pub fn new(cave: &Cave, pos: (i32, i32)) -> Self {
+    let geo_index = if pos == cave.target || pos == (0, 0) {
       0
     } else if pos.1 == 0 {
       pos.0 * 16807
     } else if pos.0 == 0 {
       pos.1 * 48271
     } else {
-      let val1;
-      if let Some(field) = map.get(&(pos.0 - 1, pos.1)) {
-        val1 = field.erosion_level;
-      } else {
-        val1 =
-          FieldInfo::new(map, target, (pos.0 - 1, pos.1), depth).erosion_level;
-      }
-      let val2;
-      if let Some(field) = map.get(&(pos.0, pos.1 - 1)) {
-        val2 = field.erosion_level;
-      } else {
-        val2 =
-          FieldInfo::new(map, target, (pos.0, pos.1 - 1), depth).erosion_level;
-      }
+      let val1 = cave.fields[cave.index((pos.0 - 1, pos.1))].erosion_level;
+      let val2 = cave.fields[cave.index((pos.0, pos.1 - 1))].erosion_level;
       val1 * val2
     };
-    let erosion_level = (geo_index + depth) % 20183;
-    let r#type = match erosion_level % 3 {
-      0 => '.',
-      1 => '=',
-      _ => '|',
-    };
-    let field = Self {
+    let erosion_level = (geo_index + cave.depth) % 20183;
+    let r#type =
+      cave.region_types[(erosion_level as usize) % cave.region_types.len()];
+
+    Self {
       erosion_level,
       r#type,
-    };
-    map.insert(pos, field);
-
-    field
+    }
   }
 }
 
@@ -190,6 +392,19 @@ impl FieldInfo {
 mod tests {
   use super::*;
 
+  fn replay(rules: &Rules, route: &Route) -> Vec<((i32, i32), Tool)> {
+    let mut states = vec![((0, 0), rules.start_tool)];
+    for step in route.steps.iter() {
+      let (mut pos, mut tool) = *states.last().unwrap();
+      match step.action {
+        Action::Move(next) => pos = next,
+        Action::Switch(next) => tool = next,
+      }
+      states.push((pos, tool));
+    }
+    states
+  }
+
   #[test]
   fn part1_test() {
     let depth = 510;
@@ -202,7 +417,100 @@ mod tests {
   fn part2_test() {
     let depth = 510;
     let target = (10, 10);
-    let mut map = build_map(depth, &target);
-    assert_eq!(find_fastest_way(&mut map, depth, &target), 45)
+    let map = build_map(depth, &target);
+    assert_eq!(find_fastest_way(&map, &Rules::default()).time, 45)
+  }
+
+  #[test]
+  fn route_test() {
+    let depth = 510;
+    let target = (10, 10);
+    let map = build_map(depth, &target);
+    let rules = Rules::default();
+    let route = find_fastest_way(&map, &rules);
+    let switches = route
+      .steps
+      .iter()
+      .filter(|s| matches!(s.action, Action::Switch(_)))
+      .count();
+    assert_eq!(route.steps.len() - switches, 24);
+    assert_eq!(switches, 3);
+    assert_eq!(route.steps.last().unwrap().time, 45);
+    assert_eq!(replay(&rules, &route).last(), Some(&(target, Tool::TORCH)));
+
+    let rendered = render_route(&map, &rules, &route);
+    assert!(rendered.starts_with("M"));
+    assert_eq!(rendered.lines().nth(10).unwrap().chars().nth(10), Some('T'));
+  }
+
+  #[test]
+  fn k_best_routes_test() {
+    let depth = 510;
+    let target = (10, 10);
+    let map = build_map(depth, &target);
+    let routes = find_fastest_ways(&map, &Rules::default(), 5);
+    assert_eq!(routes.len(), 5);
+    assert_eq!(routes[0].time, 45);
+    for i in 1..routes.len() {
+      assert!(routes[i - 1].time <= routes[i].time);
+      assert!(routes[..i].iter().all(|r| r.steps != routes[i].steps));
+    }
+  }
+
+  #[test]
+  fn bounded_cave_test() {
+    let depth = 510;
+    let target = (10, 10);
+    let map = Cave::new(depth, &target, target, &Rules::default());
+    assert_eq!(map.fields.len(), 121);
+    assert_eq!(map.get((11, 0)).map(|f| f.r#type), None);
+    assert_eq!(find_fastest_way(&map, &Rules::default()).time, 55);
+  }
+
+  #[test]
+  fn custom_costs_test() {
+    let depth = 510;
+    let target = (10, 10);
+    let map = build_map(depth, &target);
+    let rules = Rules {
+      switch_cost: 1,
+      ..Rules::default()
+    };
+    assert_eq!(find_fastest_way(&map, &rules).time, 25);
+    let rules = Rules {
+      move_cost: 2,
+      ..Rules::default()
+    };
+    assert!(find_fastest_way(&map, &rules).time > 45);
+  }
+
+  #[test]
+  fn extra_regions_and_tools_test() {
+    let depth = 510;
+    let target = (10, 10);
+    let mut rules = Rules::default();
+    let boots = Tool(3);
+    rules.region_types.push('~');
+    rules.tools.push('b');
+    rules.eligible_tools.insert('~', vec![boots]);
+    rules.eligible_tools.get_mut(&'.').unwrap().push(boots);
+    let map = Cave::new(depth, &target, (30, 30), &rules);
+    assert!(map.fields.iter().any(|f| f.r#type == '~'));
+
+    let route = find_fastest_way(&map, &rules);
+    for (pos, tool) in replay(&rules, &route) {
+      let region_type = map.get(pos).unwrap().r#type;
+      assert!(rules.eligible_tools(region_type).contains(&tool));
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "tool 3 is not one of the 3 tools")]
+  fn unknown_tool_test() {
+    let target = (10, 10);
+    let mut rules = Rules::default();
+    rules.eligible_tools.get_mut(&'.').unwrap().push(Tool(3));
+    let map = Cave::new(510, &target, (30, 30), &rules);
+    find_fastest_way(&map, &rules);
   }
 }
@@ -1,5 +1,6 @@
 use regex::Regex;
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use std::fs::File;
 use std::io::prelude::*;
 
@@ -14,44 +15,72 @@ fn main() {
     "Day 23: Experimental Emergency Teleportation part2 solution\n{:?}",
     find_dist_of_coord_that_covers_most_bots(&nanobots)
   );
+
+  if std::env::args().any(|arg| arg == "--swarm") {
+    let best = find_coord_that_covers_most_bots(&nanobots);
+    let swarm = Swarm::new(nanobots);
+    println!(
+      "{} overlapping pairs, largest group overlapping each other: {} bots",
+      swarm.overlapping_pairs().len(),
+      swarm.max_clique().len()
+    );
+    println!(
+      "{:?} is covered by {} bots, {} reach the 3x3x3 box around it",
+      best.pos,
+      swarm.covering(best.pos).len(),
+      swarm.count_covering_box(
+        (best.pos.0 - 1, best.pos.1 - 1, best.pos.2 - 1),
+        (best.pos.0 + 1, best.pos.1 + 1, best.pos.2 + 1)
+      )
+    );
+  }
 }
 
 fn find_dist_of_coord_that_covers_most_bots(nanobots: &Vec<Nanobot>) -> i64 {
-  let mut ranges_bounds = BTreeMap::new();
+  find_coord_that_covers_most_bots(nanobots).distance
+}
+
+// Branch and bound over an octree of boxes. The number of bots that reach a
+// box is an upper bound for every coordinate inside it, so the first single
+// coordinate box that gets popped is the best one.
+fn find_coord_that_covers_most_bots(nanobots: &[Nanobot]) -> BestCoordinate {
+  let mut min = (0, 0, 0);
+  let mut max = (0, 0, 0);
   for nb in nanobots.iter() {
-    let dist = nb.pos.0 + nb.pos.1 + nb.pos.2;
-    *ranges_bounds.entry(dist - nb.radius).or_insert(0) += 1;
-    *ranges_bounds.entry(dist + nb.radius + 1).or_insert(0) -= 1;
-  }
-  let mut intervals = Vec::new();
-  let mut prev = Vec::new();
-  let mut max = i64::min_value();
-  let mut val = 0;
-  for (i, (from, count)) in ranges_bounds.iter().enumerate() {
-    val += count;
-    if max < val {
-      max = val;
-    }
-    prev.push((from, val));
-    if i > 0 {
-      intervals.push(((prev[i - 1].0, from - 1), prev[i - 1].1));
-    }
+    min.0 = nb.pos.0.min(min.0);
+    min.1 = nb.pos.1.min(min.1);
+    min.2 = nb.pos.2.min(min.2);
+    max.0 = nb.pos.0.max(max.0);
+    max.1 = nb.pos.1.max(max.1);
+    max.2 = nb.pos.2.max(max.2);
+  }
+  let span = (max.0 - min.0).max(max.1 - min.1).max(max.2 - min.2) + 1;
+  let mut size = 1;
+  while size < span {
+    size *= 2;
   }
 
-  let max_intervals = intervals.iter().filter(|a| a.1 == max);
-  let mut min_dist = i64::max_value();
-  for interval in max_intervals {
-    let (from, to) = interval.0;
-    let val = if from.signum() == to.signum() {
-      from.abs().min(to.abs())
-    } else {
-      return 0;
-    };
-    if min_dist > val {
-      min_dist = val;
+  let mut heap = BinaryHeap::new();
+  heap.push(SearchBox::new(min, size, nanobots));
+  while let Some(search_box) = heap.pop() {
+    if search_box.size == 1 {
+      return BestCoordinate {
+        pos: search_box.min,
+        bots_in_range: search_box.bots_in_range,
+        distance: search_box.distance,
+      };
+    }
+    let half = search_box.size / 2;
+    for &dx in [0, half].iter() {
+      for &dy in [0, half].iter() {
+        for &dz in [0, half].iter() {
+          let (x, y, z) = search_box.min;
+          heap.push(SearchBox::new((x + dx, y + dy, z + dz), half, nanobots));
+        }
+      }
     }
   }
-  min_dist
+  unreachable!()
 }
 
 fn find_in_range_for_strongest_nanobot(nanobots: &Vec<Nanobot>) -> i64 {
@@ -109,6 +138,205 @@ impl Nanobot {
   pub fn is_in_range(&self, other: &Nanobot) -> bool {
     calculate_distance(self, other) <= self.radius
   }
+
+  pub fn covers(&self, pos: (i64, i64, i64)) -> bool {
+    distance_to_box(self.pos, pos, pos) <= self.radius
+  }
+
+  pub fn overlaps(&self, other: &Nanobot) -> bool {
+    calculate_distance(self, other) <= self.radius + other.radius
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BestCoordinate {
+  pos: (i64, i64, i64),
+  bots_in_range: usize,
+  distance: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SearchBox {
+  min: (i64, i64, i64),
+  size: i64,
+  bots_in_range: usize,
+  distance: i64,
+}
+impl SearchBox {
+  pub fn new(min: (i64, i64, i64), size: i64, nanobots: &[Nanobot]) -> Self {
+    let max = (min.0 + size - 1, min.1 + size - 1, min.2 + size - 1);
+    let bots_in_range = nanobots
+      .iter()
+      .filter(|nb| distance_to_box(nb.pos, min, max) <= nb.radius)
+      .count();
+    Self {
+      min,
+      size,
+      bots_in_range,
+      distance: distance_to_box((0, 0, 0), min, max),
+    }
+  }
+}
+impl Ord for SearchBox {
+  fn cmp(&self, other: &SearchBox) -> Ordering {
+    self
+      .bots_in_range
+      .cmp(&other.bots_in_range)
+      .then(other.distance.cmp(&self.distance))
+      .then(other.size.cmp(&self.size))
+  }
+}
+impl PartialOrd for SearchBox {
+  fn partial_cmp(&self, other: &SearchBox) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+// `min` and `max` are both inclusive corners of the box.
+fn distance_to_box(
+  pos: (i64, i64, i64),
+  min: (i64, i64, i64),
+  max: (i64, i64, i64),
+) -> i64 {
+  let axis = |p: i64, from: i64, to: i64| {
+    if p < from {
+      from - p
+    } else if p > to {
+      p - to
+    } else {
+      0
+    }
+  };
+  axis(pos.0, min.0, max.0)
+    + axis(pos.1, min.1, max.1)
+    + axis(pos.2, min.2, max.2)
+}
+
+type BitSet = Vec<u64>;
+
+fn bitset_with(len: usize, items: impl Iterator<Item = usize>) -> BitSet {
+  let mut set = vec![0; len.div_ceil(64)];
+  for i in items {
+    set[i / 64] |= 1 << (i % 64);
+  }
+  set
+}
+
+fn bitset_items(set: &BitSet) -> impl Iterator<Item = usize> + '_ {
+  set.iter().enumerate().flat_map(|(word, bits)| {
+    (0..64)
+      .filter(move |bit| bits & (1 << bit) != 0)
+      .map(move |bit| word * 64 + bit)
+  })
+}
+
+fn bitset_and(a: &BitSet, b: &BitSet) -> BitSet {
+  a.iter().zip(b.iter()).map(|(x, y)| x & y).collect()
+}
+
+fn bitset_count(set: &BitSet) -> usize {
+  set.iter().map(|bits| bits.count_ones() as usize).sum()
+}
+
+#[derive(Debug, Clone)]
+struct Swarm {
+  bots: Vec<Nanobot>,
+  overlaps: Vec<BitSet>,
+}
+impl Swarm {
+  pub fn new(bots: Vec<Nanobot>) -> Self {
+    let overlaps = (0..bots.len())
+      .map(|i| {
+        let neighbours =
+          (0..bots.len()).filter(|&j| i != j && bots[i].overlaps(&bots[j]));
+        bitset_with(bots.len(), neighbours)
+      })
+      .collect();
+    Self { bots, overlaps }
+  }
+
+  pub fn covering(&self, pos: (i64, i64, i64)) -> Vec<usize> {
+    (0..self.bots.len())
+      .filter(|&i| self.bots[i].covers(pos))
+      .collect()
+  }
+
+  pub fn count_covering_box(
+    &self,
+    min: (i64, i64, i64),
+    max: (i64, i64, i64),
+  ) -> usize {
+    self
+      .bots
+      .iter()
+      .filter(|nb| distance_to_box(nb.pos, min, max) <= nb.radius)
+      .count()
+  }
+
+  pub fn overlapping_pairs(&self) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for (i, neighbours) in self.overlaps.iter().enumerate() {
+      for j in bitset_items(neighbours).filter(|&j| j > i) {
+        pairs.push((i, j));
+      }
+    }
+    pairs
+  }
+
+  // Bron-Kerbosch with pivoting on bitsets of bot indices.
+  pub fn max_clique(&self) -> Vec<usize> {
+    let mut best = Vec::new();
+    let len = self.bots.len();
+    self.bron_kerbosch(
+      &mut Vec::new(),
+      bitset_with(len, 0..len),
+      bitset_with(len, 0..0),
+      &mut best,
+    );
+    best.sort();
+    best
+  }
+
+  fn bron_kerbosch(
+    &self,
+    clique: &mut Vec<usize>,
+    mut candidates: BitSet,
+    mut excluded: BitSet,
+    best: &mut Vec<usize>,
+  ) {
+    let candidate_count = bitset_count(&candidates);
+    if candidate_count == 0 {
+      if bitset_count(&excluded) == 0 && clique.len() > best.len() {
+        *best = clique.clone();
+      }
+      return;
+    }
+    if clique.len() + candidate_count <= best.len() {
+      return;
+    }
+    let pivot = bitset_items(&candidates)
+      .chain(bitset_items(&excluded))
+      .max_by_key(|&v| {
+        bitset_count(&bitset_and(&self.overlaps[v], &candidates))
+      })
+      .unwrap();
+    let branches = bitset_items(&candidates)
+      .filter(|&v| self.overlaps[pivot][v / 64] & (1 << (v % 64)) == 0)
+      .collect::<Vec<usize>>();
+    for v in branches {
+      let neighbours = &self.overlaps[v];
+      clique.push(v);
+      self.bron_kerbosch(
+        clique,
+        bitset_and(&candidates, neighbours),
+        bitset_and(&excluded, neighbours),
+        best,
+      );
+      clique.pop();
+      candidates[v / 64] &= !(1 << (v % 64));
+      excluded[v / 64] |= 1 << (v % 64);
+    }
+  }
 }
 
 #[cfg(test)]
@@ -126,4 +354,45 @@ mod tests {
     let nanobots = read_input("test-input2.txt");
     assert_eq!(find_dist_of_coord_that_covers_most_bots(&nanobots), 36);
   }
+
+  #[test]
+  fn best_coordinate_test() {
+    let nanobots = read_input("test-input2.txt");
+    assert_eq!(
+      find_coord_that_covers_most_bots(&nanobots),
+      BestCoordinate {
+        pos: (12, 12, 12),
+        bots_in_range: 5,
+        distance: 36,
+      }
+    );
+
+    // the sum projection sees all three bots overlapping at x + y + z = 0
+    let nanobots = vec![
+      Nanobot::new((10, -10, 0), 1),
+      Nanobot::new((-10, 10, 0), 1),
+      Nanobot::new((0, 0, 0), 1),
+    ];
+    let best = find_coord_that_covers_most_bots(&nanobots);
+    assert_eq!(best.bots_in_range, 1);
+    assert_eq!(best.distance, 0);
+  }
+
+  #[test]
+  fn swarm_test() {
+    let swarm = Swarm::new(read_input("test-input2.txt"));
+    assert_eq!(swarm.covering((12, 12, 12)), vec![0, 1, 2, 3, 4]);
+    assert_eq!(swarm.covering((50, 50, 50)), vec![4]);
+    assert_eq!(swarm.count_covering_box((12, 12, 12), (12, 12, 12)), 5);
+    assert_eq!(swarm.count_covering_box((0, 0, 0), (20, 20, 20)), 6);
+    assert_eq!(
+      swarm.count_covering_box((300, 300, 300), (400, 400, 400)),
+      0
+    );
+
+    let pairs = swarm.overlapping_pairs();
+    assert!(pairs.contains(&(0, 1)));
+    assert!(!pairs.contains(&(2, 5)));
+    assert_eq!(swarm.max_clique(), vec![0, 1, 2, 3, 4]);
+  }
 }
@@ -4,11 +4,18 @@ use std::collections::HashSet;
 use std::fs::File;
 use std::io::prelude::*;
 
+const IMMUNE_SYSTEM: &str = "Immune System";
+
 fn main() {
   let combat = read_input("input.txt");
+  let mut part1_combat = combat.clone();
+  let (result, transcript) = part1_combat.fight_with_transcript();
+  if std::env::args().any(|arg| arg == "--transcript") {
+    print!("{}", part1_combat.render_transcript(&transcript));
+  }
   println!(
     "Day 24: Immune System Simulator 20XX part1 solution\n{:?}",
-    &mut combat.clone().fight(0).0
+    result.units()
   );
 
   println!(
@@ -18,177 +25,349 @@ fn main() {
 }
 
 fn find_unit_count_in_win_with_smallest_boost(combat: &Combat) -> i32 {
-  let mut prev_boost = 0;
-  let mut step = 1000;
-  let mut prev_outcome = (22859, GroupType::Infection);
-  loop {
+  find_smallest_winning_boost(combat, IMMUNE_SYSTEM)
+    .expect("The immune system can't win with any boost")
+    .1
+}
+
+const MAX_BOOST: i32 = 1 << 16;
+const SCAN_WINDOW: i32 = 32;
+
+// Returns the smallest boost that lets `army` win together with the units it
+// has left. Outcomes aren't monotone in the boost (stalemates can show up
+// after a win), so the bisection only narrows the range and the last stretch
+// below the found boundary is scanned one boost at a time.
+fn find_smallest_winning_boost(
+  combat: &Combat,
+  army: &str,
+) -> Option<(i32, i32)> {
+  let army_units = |boost: i32| {
     let mut curr_combat = combat.clone();
-    let boost = prev_boost + step;
-    let curr_outcome = curr_combat.fight(boost);
-    if step == 0 || curr_outcome.1 != prev_outcome.1 && step.abs() == 1 {
-      if curr_outcome.1 == GroupType::ImmuneSystem {
-        return curr_outcome.0;
-      } else {
-        return prev_outcome.0;
-      };
+    curr_combat.boost(army, boost);
+    match curr_combat.fight() {
+      FightResult::Victory(winner, units) if winner == army => Some(units),
+      _ => None,
+    }
+  };
+
+  let mut lo = 0;
+  if let Some(units) = army_units(lo) {
+    return Some((lo, units));
+  }
+  let mut hi = 1;
+  while army_units(hi).is_none() {
+    if hi >= MAX_BOOST {
+      return None;
     }
+    lo = hi;
+    hi *= 2;
+  }
 
-    if curr_outcome.1 != prev_outcome.1 {
-      step = -step / 2;
+  while hi - lo > 1 {
+    let mid = lo + (hi - lo) / 2;
+    if army_units(mid).is_some() {
+      hi = mid;
+    } else {
+      lo = mid;
     }
-    prev_outcome = curr_outcome;
-    prev_boost = boost;
   }
+
+  ((hi - SCAN_WINDOW).max(1)..=hi)
+    .find_map(|boost| army_units(boost).map(|units| (boost, units)))
 }
 
+// Groups are never removed, so a group's index in `groups` identifies it for
+// the whole fight no matter what its initiative is.
 #[derive(Debug, Clone)]
 struct Combat {
-  immune_system: Vec<Group>,
-  infections: Vec<Group>,
+  armies: Vec<String>,
+  groups: Vec<Group>,
 }
 impl Combat {
-  pub fn new(immune_system: Vec<Group>, infections: Vec<Group>) -> Self {
-    Self {
-      immune_system,
-      infections,
+  pub fn new(armies: Vec<String>, groups: Vec<Group>) -> Self {
+    Self { armies, groups }
+  }
+
+  pub fn boost(&mut self, army: &str, boost: i32) {
+    let army = self.army_index(army);
+    for item in self.groups.iter_mut().filter(|g| g.army == army) {
+      item.attack_damage += boost;
     }
   }
 
-  pub fn fight(&mut self, boost: i32) -> (i32, GroupType) {
-    if boost > 0 {
-      for item in self.immune_system.iter_mut() {
-        item.attack_damage += boost;
+  pub fn fight(&mut self) -> FightResult {
+    self.run(None)
+  }
+
+  pub fn fight_with_transcript(&mut self) -> (FightResult, Vec<Round>) {
+    let mut transcript = Vec::new();
+    let result = self.run(Some(&mut transcript));
+    (result, transcript)
+  }
+
+  fn run(&mut self, mut transcript: Option<&mut Vec<Round>>) -> FightResult {
+    while self.armies_left().len() > 1 {
+      let mut round = Round::default();
+      if transcript.is_some() {
+        round.tallies = self.tallies();
       }
-    }
-    while !self.immune_system.is_empty() && !self.infections.is_empty() {
+
       // target selection
-      self.immune_system.sort();
-      self.infections.sort();
-      for is in self.immune_system.iter_mut() {
-        is.target = find_target(is, &mut self.infections);
-      }
-      for inf in self.infections.iter_mut() {
-        inf.target = find_target(inf, &mut self.immune_system);
+      let mut selection_order = self.alive();
+      selection_order.sort_by(|&a, &b| self.groups[a].cmp(&self.groups[b]));
+      let mut taken = HashSet::new();
+      let mut targets = vec![None; self.groups.len()];
+      for &attacker in selection_order.iter() {
+        targets[attacker] = self.find_target(attacker, &taken);
+        if let Some(defender) = targets[attacker] {
+          taken.insert(defender);
+          if transcript.is_some() {
+            round.selections.push(Attack {
+              attacker,
+              defender,
+              damage: self.groups[attacker]
+                .calculate_damage(&self.groups[defender]),
+              killed_units: 0,
+            });
+          }
+        }
       }
 
       // attacking phase
-      let mut combined = Vec::new();
-      combined.append(&mut self.immune_system);
-      combined.append(&mut self.infections);
-      combined.sort_by(|a, b| b.initiative.cmp(&a.initiative));
+      let mut attack_order = self.alive();
+      attack_order.sort_by_key(|&i| -self.groups[i].initiative);
       let mut total_kills = 0;
-      for i in 0..combined.len() {
-        if combined[i].units > 0 && combined[i].target.is_some() {
-          let target_initiative = combined[i].target.unwrap();
-          let target_index = combined
-            .iter()
-            .position(|a| a.initiative == target_initiative)
-            .unwrap();
-          let target = combined[target_index].clone();
-          let damage = combined[i].calculate_damage(&target);
-          let killed_units = damage / combined[target_index].hit_points;
-          combined[target_index].units -= killed_units;
+      for attacker in attack_order {
+        if let (true, Some(defender)) =
+          (self.groups[attacker].units > 0, targets[attacker])
+        {
+          let target = &self.groups[defender];
+          let damage = self.groups[attacker].calculate_damage(target);
+          let killed_units = (damage / target.hit_points).min(target.units);
+          self.groups[defender].units -= killed_units;
           total_kills += killed_units;
+          if transcript.is_some() {
+            round.attacks.push(Attack {
+              attacker,
+              defender,
+              damage,
+              killed_units,
+            });
+          }
         }
       }
 
-      // remove killed groups
-      for item in combined.iter_mut() {
-        if item.units > 0 {
-          item.taken = false;
-          if item.group_type == GroupType::ImmuneSystem {
-            self.immune_system.push(item.clone());
-          } else {
-            self.infections.push(item.clone());
-          }
-        }
+      if let Some(transcript) = transcript.as_mut() {
+        transcript.push(round);
       }
       if total_kills == 0 {
-        break;
+        return FightResult::Stalemate(
+          self
+            .armies_left()
+            .into_iter()
+            .map(|army| (self.armies[army].clone(), self.army_units(army)))
+            .collect(),
+        );
       }
     }
 
-    if !self.infections.is_empty() {
-      (
-        self.infections.iter().fold(0, |acc, g| acc + g.units),
-        GroupType::Infection,
-      )
-    } else {
-      (
-        self.immune_system.iter().fold(0, |acc, g| acc + g.units),
-        GroupType::ImmuneSystem,
-      )
+    match self.armies_left().first() {
+      Some(&army) => {
+        FightResult::Victory(self.armies[army].clone(), self.army_units(army))
+      }
+      None => FightResult::Stalemate(Vec::new()),
     }
   }
-}
 
-fn find_target(
-  group: &mut Group,
-  potential_targets: &mut Vec<Group>,
-) -> Option<i32> {
-  let mut candidates = Vec::new();
-  for pt in potential_targets.iter() {
-    let damage = group.calculate_damage(&pt);
-    if !pt.taken && damage > 0 {
-      candidates.push((damage, pt.effective_power(), pt.initiative));
+  fn find_target(
+    &self,
+    attacker: usize,
+    taken: &HashSet<usize>,
+  ) -> Option<usize> {
+    let group = &self.groups[attacker];
+    let mut candidates = Vec::new();
+    for pt in self.alive() {
+      let target = &self.groups[pt];
+      let damage = group.calculate_damage(target);
+      if target.army != group.army && !taken.contains(&pt) && damage > 0 {
+        candidates.push((
+          damage,
+          target.effective_power(),
+          target.initiative,
+          pt,
+        ));
+      }
     }
+
+    candidates.sort_by(|a, b| {
+      b.0
+        .cmp(&a.0)
+        .then(b.1.cmp(&a.1))
+        .then(b.2.cmp(&a.2))
+        .then(a.3.cmp(&b.3))
+    });
+    candidates.first().map(|c| c.3)
   }
 
-  if candidates.is_empty() {
-    None
-  } else {
-    candidates
-      .sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(b.2.cmp(&a.2)));
-    let (_damage, _, initiative) = candidates[0];
-    for item in potential_targets.iter_mut() {
-      if item.initiative == initiative {
-        item.taken = true;
-        break;
+  fn alive(&self) -> Vec<usize> {
+    (0..self.groups.len())
+      .filter(|&i| self.groups[i].units > 0)
+      .collect()
+  }
+
+  fn armies_left(&self) -> Vec<usize> {
+    (0..self.armies.len())
+      .filter(|&army| self.army_units(army) > 0)
+      .collect()
+  }
+
+  fn army_units(&self, army: usize) -> i32 {
+    self
+      .groups
+      .iter()
+      .filter(|g| g.army == army)
+      .fold(0, |acc, g| acc + g.units)
+  }
+
+  fn army_index(&self, army: &str) -> usize {
+    self
+      .armies
+      .iter()
+      .position(|name| name == army)
+      .unwrap_or_else(|| panic!("unknown army {}", army))
+  }
+
+  fn tallies(&self) -> Vec<(usize, i32)> {
+    let mut tallies = self
+      .alive()
+      .into_iter()
+      .map(|i| (i, self.groups[i].units))
+      .collect::<Vec<_>>();
+    tallies.sort_by_key(|&(i, _)| (self.groups[i].army, self.groups[i].id));
+    tallies
+  }
+
+  pub fn render_transcript(&self, transcript: &[Round]) -> String {
+    let mut res = String::new();
+    let name = |i: usize| &self.armies[self.groups[i].army];
+    for round in transcript.iter() {
+      for (army, army_name) in self.armies.iter().enumerate() {
+        res.push_str(&format!("{}:\n", army_name));
+        let groups = round
+          .tallies
+          .iter()
+          .filter(|&&(i, _)| self.groups[i].army == army)
+          .collect::<Vec<_>>();
+        if groups.is_empty() {
+          res.push_str("No groups remain.\n");
+        }
+        for (i, units) in groups {
+          res.push_str(&format!(
+            "Group {} contains {} units\n",
+            self.groups[*i].id, units
+          ));
+        }
+      }
+      res.push('\n');
+      for s in round.selections.iter() {
+        res.push_str(&format!(
+          "{} group {} would deal defending group {} {} damage\n",
+          name(s.attacker),
+          self.groups[s.attacker].id,
+          self.groups[s.defender].id,
+          s.damage
+        ));
+      }
+      res.push('\n');
+      for a in round.attacks.iter() {
+        res.push_str(&format!(
+          "{} group {} attacks defending group {}, dealing {} damage and killing {} units\n",
+          name(a.attacker),
+          self.groups[a.attacker].id,
+          self.groups[a.defender].id,
+          a.damage,
+          a.killed_units
+        ));
       }
+      res.push('\n');
     }
-    Some(initiative)
+    res
   }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FightResult {
+  Victory(String, i32),
+  // units left in every army that still has any
+  Stalemate(Vec<(String, i32)>),
+}
+impl FightResult {
+  pub fn units(&self) -> i32 {
+    match self {
+      FightResult::Victory(_, units) => *units,
+      FightResult::Stalemate(armies) => {
+        armies.iter().map(|(_, units)| units).sum()
+      }
+    }
+  }
+}
+
+// `attacker` and `defender` are indices into `Combat::groups`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Attack {
+  attacker: usize,
+  defender: usize,
+  damage: i32,
+  killed_units: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Round {
+  // units left in each living group at the start of the round
+  tallies: Vec<(usize, i32)>,
+  selections: Vec<Attack>,
+  attacks: Vec<Attack>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct Group {
+  army: usize,
+  // group number within its army, as used in the transcript
+  id: usize,
   units: i32,
   hit_points: i32,
-  group_type: GroupType,
   attack_damage: i32,
-  attack_type: AttackType,
+  attack_type: String,
   initiative: i32,
-  weaknesses: HashSet<AttackType>,
-  immunities: HashSet<AttackType>,
-  target: Option<i32>,
-  taken: bool,
+  weaknesses: HashSet<String>,
+  immunities: HashSet<String>,
 }
 impl Group {
   pub fn new(
+    army: usize,
+    id: usize,
     units: i32,
     hit_points: i32,
-    group_type: GroupType,
     attack_damage: i32,
-    attack_type: AttackType,
+    attack_type: String,
     initiative: i32,
-    weaknesses: HashSet<AttackType>,
-    immunities: HashSet<AttackType>,
+    weaknesses: HashSet<String>,
+    immunities: HashSet<String>,
   ) -> Self {
     Self {
+      army,
+      id,
       units,
       hit_points,
-      group_type,
       attack_damage,
       attack_type,
       initiative,
       weaknesses,
       immunities,
-      target: None,
-      taken: false,
     }
   }
 
-  fn calculate_damage(&mut self, other: &Group) -> i32 {
+  fn calculate_damage(&self, other: &Group) -> i32 {
     let mut damage = self.effective_power();
     if other.immunities.contains(&self.attack_type) {
       damage = 0;
@@ -212,33 +391,7 @@ impl Ord for Group {
 }
 impl PartialOrd for Group {
   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-    Some(self.cmp(&other))
-  }
-}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum GroupType {
-  ImmuneSystem,
-  Infection,
-}
-
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-enum AttackType {
-  Cold,
-  Radiation,
-  Slashing,
-  Fire,
-  Bludgeoning,
-}
-
-fn get_attack_type(type_str: &str) -> AttackType {
-  match type_str {
-    "cold" => AttackType::Cold,
-    "radiation" => AttackType::Radiation,
-    "slashing" => AttackType::Slashing,
-    "fire" => AttackType::Fire,
-    "bludgeoning" => AttackType::Bludgeoning,
-    _ => panic!("unexpected value {}", type_str),
+    Some(self.cmp(other))
   }
 }
 
@@ -249,13 +402,16 @@ fn read_input(filename: &str) -> Combat {
     .read_to_string(&mut content)
     .expect("Failed to read input file");
 
-  let mut immune_system = Vec::new();
-  let mut infections = Vec::new();
-  let group_contents = content.split_terminator("\n\n").collect::<Vec<&str>>();
+  let mut armies = Vec::new();
+  let mut groups = Vec::new();
+  let army_contents = content.split_terminator("\n\n").collect::<Vec<&str>>();
   let re = Regex::new(r"(\d+) units each with (\d+) hit points (\(.*?\))?\s?with an attack that does (\d+) ([a-z]+) damage at initiative (\d+)").unwrap();
-  let mut group_type = GroupType::ImmuneSystem;
-  for gc in group_contents {
-    for s in gc.split_terminator('\n').skip(1) {
+  for ac in army_contents {
+    let mut lines = ac.split_terminator('\n');
+    let header = lines.next().expect("Missing army name");
+    let army = armies.len();
+    armies.push(header.trim().trim_end_matches(':').to_string());
+    for (i, s) in lines.enumerate() {
       let caps = re.captures(s).unwrap();
       let units = caps[1].parse::<i32>().unwrap();
       let hit_points = caps[2].parse::<i32>().unwrap();
@@ -271,55 +427,66 @@ fn read_input(filename: &str) -> Combat {
         for st in strs {
           if st.starts_with("weak to ") {
             for type_str in st.split_at(8).1.split_terminator(", ") {
-              weaknesses.insert(get_attack_type(type_str.trim()));
+              weaknesses.insert(type_str.trim().to_string());
             }
           } else {
             for type_str in st.split_at(9).1.split_terminator(", ") {
-              immunities.insert(get_attack_type(type_str.trim()));
+              immunities.insert(type_str.trim().to_string());
             }
           }
         }
       }
 
       let attack_damage = caps[4].parse::<i32>().unwrap();
-      let attack_type = get_attack_type(&caps[5]);
+      let attack_type = caps[5].to_string();
       let initiative = caps[6].parse::<i32>().unwrap();
-      let group = Group::new(
+      groups.push(Group::new(
+        army,
+        i + 1,
         units,
         hit_points,
-        group_type,
         attack_damage,
         attack_type,
         initiative,
         weaknesses,
         immunities,
-      );
-      if group_type == GroupType::ImmuneSystem {
-        immune_system.push(group);
-      } else {
-        infections.push(group);
-      }
+      ));
     }
-    group_type = GroupType::Infection;
   }
 
-  Combat::new(immune_system, infections)
+  Combat::new(armies, groups)
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  fn make_immune(combat: &mut Combat, army: &str, attack_types: &[&str]) {
+    let army = combat.army_index(army);
+    for group in combat.groups.iter_mut().filter(|g| g.army == army) {
+      for attack_type in attack_types {
+        group.immunities.insert(attack_type.to_string());
+      }
+    }
+  }
+
   #[test]
   fn part1_test() {
     let mut combat = read_input("test-input.txt");
-    assert_eq!(combat.fight(0), (5216, GroupType::Infection));
+    assert_eq!(
+      combat.fight(),
+      FightResult::Victory("Infection".to_string(), 5216)
+    );
   }
 
   #[test]
   fn part2_test1() {
     let mut combat = read_input("test-input.txt");
-    assert_eq!(combat.fight(1570), (51, GroupType::ImmuneSystem));
+    combat.boost(IMMUNE_SYSTEM, 1570);
+    assert_eq!(
+      combat.fight(),
+      FightResult::Victory(IMMUNE_SYSTEM.to_string(), 51)
+    );
   }
 
   #[test]
@@ -327,4 +494,80 @@ mod tests {
     let combat = read_input("test-input.txt");
     assert_eq!(find_unit_count_in_win_with_smallest_boost(&combat), 51);
   }
+
+  #[test]
+  fn smallest_boost_test() {
+    let combat = read_input("test-input.txt");
+    assert_eq!(
+      find_smallest_winning_boost(&combat, IMMUNE_SYSTEM),
+      Some((1570, 51))
+    );
+
+    let mut combat = read_input("test-input.txt");
+    make_immune(&mut combat, "Infection", &["fire", "slashing"]);
+    assert_eq!(find_smallest_winning_boost(&combat, IMMUNE_SYSTEM), None);
+  }
+
+  #[test]
+  fn stalemate_test() {
+    let mut combat = read_input("test-input.txt");
+    make_immune(&mut combat, "Infection", &["fire", "slashing"]);
+    make_immune(&mut combat, IMMUNE_SYSTEM, &["bludgeoning", "slashing"]);
+    assert_eq!(
+      combat.fight(),
+      FightResult::Stalemate(vec![
+        (IMMUNE_SYSTEM.to_string(), 1006),
+        ("Infection".to_string(), 5286),
+      ])
+    );
+  }
+
+  #[test]
+  fn transcript_test() {
+    let mut combat = read_input("test-input.txt");
+    let (result, transcript) = combat.fight_with_transcript();
+    assert_eq!(result, FightResult::Victory("Infection".to_string(), 5216));
+    assert_eq!(transcript.len(), 8);
+    let rendered = combat.render_transcript(&transcript[..1]);
+    assert_eq!(
+      rendered,
+      "Immune System:
+Group 1 contains 17 units
+Group 2 contains 989 units
+Infection:
+Group 1 contains 801 units
+Group 2 contains 4485 units
+
+Infection group 1 would deal defending group 1 185832 damage
+Immune System group 1 would deal defending group 2 153238 damage
+Infection group 2 would deal defending group 2 107640 damage
+Immune System group 2 would deal defending group 1 24725 damage
+
+Infection group 2 attacks defending group 2, dealing 107640 damage and killing 84 units
+Immune System group 2 attacks defending group 1, dealing 22625 damage and killing 4 units
+Immune System group 1 attacks defending group 2, dealing 153238 damage and killing 51 units
+Infection group 1 attacks defending group 1, dealing 184904 damage and killing 17 units
+
+"
+    );
+  }
+
+  #[test]
+  fn named_armies_test() {
+    let mut combat = read_input("test-input2.txt");
+    assert_eq!(combat.armies, vec![IMMUNE_SYSTEM, "Infection", "Parasites"]);
+    assert_eq!(combat.groups[5].attack_type, "sonic");
+
+    let (result, transcript) = combat.fight_with_transcript();
+    let attacks = transcript.iter().flat_map(|round| round.attacks.iter());
+    let mut armies_attacked = HashSet::new();
+    for attack in attacks {
+      let attacker = &combat.groups[attack.attacker];
+      let defender = &combat.groups[attack.defender];
+      assert_ne!(attacker.army, defender.army);
+      armies_attacked.insert(defender.army);
+    }
+    assert_eq!(armies_attacked.len(), 3);
+    assert_eq!(result, FightResult::Victory("Infection".to_string(), 5203));
+  }
 }
@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 
@@ -8,51 +8,235 @@ fn main() {
     "Day 25: Four-Dimensional Adventure solution\n{}",
     find_constellation_count(&points)
   );
+
+  if std::env::args().any(|arg| arg == "--stream") {
+    let mut tracker = ConstellationTracker::new(ClusterConfig::default());
+    for point in points.iter() {
+      let i = tracker.add(point.clone());
+      println!(
+        "{:?} joins a constellation of {}, {} in total",
+        point,
+        tracker.constellation_of(i).len(),
+        tracker.count()
+      );
+    }
+  }
+  if std::env::args().any(|arg| arg == "--members") {
+    let constellations =
+      find_constellations(&points, &ClusterConfig::default());
+    for (i, members) in constellations.iter().enumerate() {
+      println!(
+        "Constellation {} ({} points): {:?}",
+        i,
+        members.len(),
+        members
+      );
+    }
+  }
 }
 
 fn find_constellation_count(points: &Vec<Vec<i32>>) -> i32 {
-  let mut graph = HashMap::new();
-  let mut remaining = HashSet::new();
-  for p1 in 0..points.len() {
-    remaining.insert(p1);
-    for p2 in 0..points.len() {
-      if p1 != p2 {
-        let distance = points[p1]
-          .iter()
-          .zip(points[p2].iter())
-          .fold(0, |acc, (x, y)| acc + (x - y).abs());
-        if distance <= 3 {
-          let neighbours = graph.entry(p1).or_insert(Vec::new());
-          neighbours.push(p2);
-        }
-      }
+  let mut tracker = ConstellationTracker::new(ClusterConfig::default());
+  for point in points.iter() {
+    tracker.add(point.clone());
+  }
+  tracker.count() as i32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ClusterConfig {
+  threshold: i32,
+  dimensions: usize,
+}
+impl Default for ClusterConfig {
+  fn default() -> Self {
+    Self {
+      threshold: 3,
+      dimensions: 4,
     }
   }
+}
 
-  let mut queue = vec![0];
-  let mut constellation_count = 0;
-  while !queue.is_empty() {
-    let curr = queue.remove(0);
-    remaining.remove(&curr);
+fn find_constellations(
+  points: &[Vec<i32>],
+  config: &ClusterConfig,
+) -> Vec<Vec<Vec<i32>>> {
+  let mut tracker = ConstellationTracker::new(*config);
+  for point in points.iter() {
+    tracker.add(point.clone());
+  }
+  tracker.constellations()
+}
 
-    // add connections
-    if let Some(neighbours) = graph.get(&curr) {
-      for n in neighbours {
-        if remaining.contains(n) {
-          queue.push(*n);
+// Points are bucketed into grid cells as wide as the threshold, so any point
+// close enough to join a constellation sits in the same or an adjacent cell.
+#[derive(Debug, Clone)]
+struct ConstellationTracker {
+  config: ClusterConfig,
+  offsets: Vec<Vec<i32>>,
+  grid: HashMap<Vec<i32>, Vec<usize>>,
+  points: Vec<Vec<i32>>,
+  sets: UnionFind,
+  count: usize,
+}
+impl ConstellationTracker {
+  pub fn new(config: ClusterConfig) -> Self {
+    Self {
+      config,
+      offsets: neighbour_offsets(config.dimensions),
+      grid: HashMap::new(),
+      points: Vec::new(),
+      sets: UnionFind::new(0),
+      count: 0,
+    }
+  }
+
+  // Adds the point and merges every constellation it bridges, returning the
+  // index the point can later be looked up by.
+  pub fn add(&mut self, point: Vec<i32>) -> usize {
+    if point.len() != self.config.dimensions {
+      panic!(
+        "expected {} coordinates, got {:?}",
+        self.config.dimensions, point
+      );
+    }
+    let i = self.sets.push();
+    self.count += 1;
+    let cell_size = self.config.threshold.max(1);
+    let cell = point
+      .iter()
+      .map(|x| x.div_euclid(cell_size))
+      .collect::<Vec<i32>>();
+    for offset in self.offsets.iter() {
+      let neighbour_cell = cell
+        .iter()
+        .zip(offset.iter())
+        .map(|(c, o)| c + o)
+        .collect::<Vec<i32>>();
+      if let Some(neighbours) = self.grid.get(&neighbour_cell) {
+        for &n in neighbours {
+          if calculate_distance(&point, &self.points[n])
+            <= self.config.threshold
+            && self.sets.union(i, n)
+          {
+            self.count -= 1;
+          }
         }
       }
     }
+    self.grid.entry(cell).or_default().push(i);
+    self.points.push(point);
+    i
+  }
 
-    if queue.is_empty() {
-      constellation_count += 1;
-      if !remaining.is_empty() {
-        queue.push(*remaining.iter().next().unwrap());
-      }
+  pub fn count(&self) -> usize {
+    self.count
+  }
+
+  // Costs as much as the constellation is big, not the whole tracker.
+  pub fn constellation_of(&mut self, i: usize) -> Vec<Vec<i32>> {
+    let points = &self.points;
+    self
+      .sets
+      .members(i)
+      .iter()
+      .map(|&j| points[j].clone())
+      .collect()
+  }
+
+  // Constellations are ordered by their first point, members by arrival.
+  pub fn constellations(&mut self) -> Vec<Vec<Vec<i32>>> {
+    let mut constellations: Vec<Vec<Vec<i32>>> = Vec::new();
+    let mut positions = HashMap::new();
+    for (i, point) in self.points.iter().enumerate() {
+      let root = self.sets.find(i);
+      let position = *positions.entry(root).or_insert_with(|| {
+        constellations.push(Vec::new());
+        constellations.len() - 1
+      });
+      constellations[position].push(point.clone());
     }
+    constellations
   }
+}
 
-  constellation_count
+fn neighbour_offsets(dimensions: usize) -> Vec<Vec<i32>> {
+  let mut offsets = vec![Vec::new()];
+  for _ in 0..dimensions {
+    offsets = offsets
+      .into_iter()
+      .flat_map(|offset| {
+        (-1..=1).map(move |d| {
+          let mut next = offset.clone();
+          next.push(d);
+          next
+        })
+      })
+      .collect();
+  }
+  offsets
+}
+
+fn calculate_distance(p1: &[i32], p2: &[i32]) -> i32 {
+  p1.iter()
+    .zip(p2.iter())
+    .fold(0, |acc, (x, y)| acc + (x - y).abs())
+}
+
+// Every root keeps the list of its set's members, the smaller list moving
+// into the bigger one on a union.
+#[derive(Debug, Clone)]
+struct UnionFind {
+  parent: Vec<usize>,
+  members: Vec<Vec<usize>>,
+}
+impl UnionFind {
+  pub fn new(len: usize) -> Self {
+    Self {
+      parent: (0..len).collect(),
+      members: (0..len).map(|i| vec![i]).collect(),
+    }
+  }
+
+  pub fn push(&mut self) -> usize {
+    let i = self.parent.len();
+    self.parent.push(i);
+    self.members.push(vec![i]);
+    i
+  }
+
+  pub fn find(&mut self, i: usize) -> usize {
+    let mut root = i;
+    while self.parent[root] != root {
+      root = self.parent[root];
+    }
+    let mut curr = i;
+    while self.parent[curr] != root {
+      let next = self.parent[curr];
+      self.parent[curr] = root;
+      curr = next;
+    }
+    root
+  }
+
+  pub fn union(&mut self, a: usize, b: usize) -> bool {
+    let (mut a, mut b) = (self.find(a), self.find(b));
+    if a == b {
+      return false;
+    }
+    if self.members[a].len() < self.members[b].len() {
+      std::mem::swap(&mut a, &mut b);
+    }
+    self.parent[b] = a;
+    let moved = std::mem::take(&mut self.members[b]);
+    self.members[a].extend(moved);
+    true
+  }
+
+  pub fn members(&mut self, i: usize) -> &[usize] {
+    let root = self.find(i);
+    &self.members[root]
+  }
 }
 
 fn read_input(filename: &str) -> Vec<Vec<i32>> {
@@ -100,4 +284,66 @@ mod tests {
     let points = read_input("test-input4.txt");
     assert_eq!(find_constellation_count(&points), 8)
   }
+
+  #[test]
+  fn constellation_members_test() {
+    let points = read_input("test-input.txt");
+    let constellations =
+      find_constellations(&points, &ClusterConfig::default());
+    assert_eq!(constellations.len(), 2);
+    assert_eq!(constellations[0].len(), 6);
+    assert_eq!(constellations[1], vec![vec![9, 0, 0, 0], vec![12, 0, 0, 0]]);
+
+    let config = ClusterConfig {
+      threshold: 2,
+      dimensions: 4,
+    };
+    assert_eq!(find_constellations(&points, &config).len(), 8);
+  }
+
+  #[test]
+  fn other_dimensions_test() {
+    let points = vec![vec![-1, 0], vec![1, 0], vec![5, 5], vec![9, 5]];
+    let config = ClusterConfig {
+      threshold: 4,
+      dimensions: 2,
+    };
+    let constellations = find_constellations(&points, &config);
+    assert_eq!(
+      constellations,
+      vec![vec![vec![-1, 0], vec![1, 0]], vec![vec![5, 5], vec![9, 5]]]
+    );
+  }
+
+  #[test]
+  fn streaming_test() {
+    let mut tracker = ConstellationTracker::new(ClusterConfig::default());
+    tracker.add(vec![0, 0, 0, 0]);
+    tracker.add(vec![6, 0, 0, 0]);
+    assert_eq!(tracker.count(), 2);
+    let bridge = tracker.add(vec![3, 0, 0, 0]);
+    assert_eq!(tracker.count(), 1);
+    let mut members = tracker.constellation_of(bridge);
+    members.sort();
+    assert_eq!(
+      members,
+      vec![vec![0, 0, 0, 0], vec![3, 0, 0, 0], vec![6, 0, 0, 0]]
+    );
+    tracker.add(vec![20, 0, 0, 0]);
+    assert_eq!(tracker.count(), 2);
+    assert_eq!(
+      tracker.constellations(),
+      vec![
+        vec![vec![0, 0, 0, 0], vec![6, 0, 0, 0], vec![3, 0, 0, 0]],
+        vec![vec![20, 0, 0, 0]]
+      ]
+    );
+
+    let points = read_input("test-input4.txt");
+    let mut tracker = ConstellationTracker::new(ClusterConfig::default());
+    for point in points {
+      tracker.add(point);
+    }
+    assert_eq!(tracker.count(), 8);
+  }
 }